@@ -0,0 +1,310 @@
+#![allow(clippy::bool_comparison)]
+
+use crate::*;
+use std::env;
+use std::ffi::OsString;
+use std::fs::read_to_string;
+use std::io::{stdin, Read};
+
+// The dircolors keywords and the LS_COLORS keys they stand for. The first keyword of each key is
+// the one used when printing a database.
+const KEYWORDS: [(&str, &str); 32] = [
+    ("NORMAL", "no"),
+    ("NORM", "no"),
+    ("FILE", "fi"),
+    ("RESET", "rs"),
+    ("DIR", "di"),
+    ("LINK", "ln"),
+    ("LNK", "ln"),
+    ("SYMLINK", "ln"),
+    ("MULTIHARDLINK", "mh"),
+    ("FIFO", "pi"),
+    ("PIPE", "pi"),
+    ("SOCK", "so"),
+    ("DOOR", "do"),
+    ("BLK", "bd"),
+    ("BLOCK", "bd"),
+    ("CHR", "cd"),
+    ("CHAR", "cd"),
+    ("ORPHAN", "or"),
+    ("MISSING", "mi"),
+    ("SETUID", "su"),
+    ("SETGID", "sg"),
+    ("CAPABILITY", "ca"),
+    ("STICKY_OTHER_WRITABLE", "tw"),
+    ("OTHER_WRITABLE", "ow"),
+    ("STICKY", "st"),
+    ("EXEC", "ex"),
+    ("LEFTCODE", "lc"),
+    ("LEFT", "lc"),
+    ("RIGHTCODE", "rc"),
+    ("RIGHT", "rc"),
+    ("ENDCODE", "ec"),
+    ("END", "ec"),
+];
+
+// The shell syntax the LS_COLORS assignment is printed in.
+#[derive(PartialEq)]
+enum ShellSyntax {
+    Bourne,
+    CShell,
+}
+
+// Returns the built-in database, i.e. DEFAULT_COLORS written in the dircolors format.
+fn get_default_database() -> String {
+    let mut database = String::from(
+        "# Configuration file for dircolors, used to set the LS_COLORS environment variable.\n\
+         # Lines are \"KEYWORD COLOR\", \".extension COLOR\" or \"*pattern COLOR\", and only\n\
+         # apply to the terminals matched by the TERM lines preceding them, if there are any.\n",
+    );
+
+    for entry in DEFAULT_COLORS.split(':') {
+        if let Some((key, value)) = entry.split_once('=') {
+            match KEYWORDS.iter().find(|(_, keyword_key)| *keyword_key == key) {
+                Some((keyword, _)) => database.push_str(&format!("{} {}\n", keyword, value)),
+                None => database.push_str(&format!("{} {}\n", key, value)),
+            }
+        }
+    }
+    database
+}
+
+// Converts a database in the dircolors format to the content of LS_COLORS, only keeping the
+// lines that apply to the terminal described by the values of TERM and COLORTERM.
+fn convert_database_to_ls_colors(
+    database: &str,
+    term: &str,
+    color_term: &str,
+) -> Result<String, String> {
+    let mut entries: Vec<String> = Vec::new();
+    // Lines before the first TERM or COLORTERM line apply to every terminal.
+    let mut terminal_matches = true;
+    let mut previous_line_was_term = false;
+
+    for (line_number, line) in database.lines().enumerate() {
+        let line = match line.find('#') {
+            Some(comment) => &line[..comment],
+            None => line,
+        }
+        .trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let (keyword, value) = match line.split_once(|c: char| c.is_whitespace()) {
+            Some((keyword, value)) => (keyword, value.trim()),
+            None => {
+                return Err(format!(
+                    "line {}: invalid line; missing second token",
+                    line_number + 1
+                ))
+            }
+        };
+
+        if keyword == "TERM" || keyword == "COLORTERM" {
+            if previous_line_was_term == false {
+                terminal_matches = false;
+            }
+            let target = if keyword == "TERM" { term } else { color_term };
+            if glob_match(value.as_bytes(), target.as_bytes()) {
+                terminal_matches = true;
+            }
+            previous_line_was_term = true;
+            continue;
+        }
+        previous_line_was_term = false;
+
+        if terminal_matches == false {
+            continue;
+        }
+        if keyword.starts_with('.') {
+            entries.push(format!("*{}={}", keyword, value));
+        } else if keyword.starts_with('*') {
+            entries.push(format!("{}={}", keyword, value));
+        } else if let Some((_, key)) = KEYWORDS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(keyword))
+        {
+            entries.push(format!("{}={}", key, value));
+        } else if ["OPTIONS", "COLOR", "EIGHTBIT"].contains(&keyword.to_ascii_uppercase().as_str())
+        {
+            // Slackware-specific keywords, ignored like GNU dircolors does.
+        } else {
+            return Err(format!(
+                "line {}: unrecognized keyword {}",
+                line_number + 1,
+                keyword
+            ));
+        }
+    }
+
+    let mut ls_colors = entries.join(":");
+    if !ls_colors.is_empty() {
+        ls_colors.push(':');
+    }
+    Ok(ls_colors)
+}
+
+// Prints the shell code setting LS_COLORS, quoting single quotes so it can be eval'ed.
+fn print_shell_code(ls_colors: &str, shell_syntax: &ShellSyntax) {
+    let quoted = ls_colors.replace('\'', "'\\''");
+
+    if *shell_syntax == ShellSyntax::CShell {
+        println!("setenv LS_COLORS '{}'", quoted);
+    } else {
+        println!("LS_COLORS='{}';", quoted);
+        println!("export LS_COLORS");
+    }
+}
+
+// Reads the database from the given file, from the standard input for "-", or uses the built-in
+// one if no file was given.
+fn read_database(file: Option<&OsString>) -> Result<String, String> {
+    match file {
+        None => Ok(get_default_database()),
+        Some(file) if file == "-" => {
+            let mut database = String::new();
+            match stdin().read_to_string(&mut database) {
+                Ok(_) => Ok(database),
+                Err(error) => Err(format!("-: {}", describe_io_error(&error))),
+            }
+        }
+        Some(file) => read_to_string(file).map_err(|error| {
+            format!(
+                "{}: {}",
+                quote_name_before_colon(file),
+                describe_io_error(&error)
+            )
+        }),
+    }
+}
+
+// Called when the --dircolors or --print-database parameter is included, replacing the listing
+// with the output of the "dircolors" command.
+pub fn handle_dircolors(args: &Vec<OsString>) {
+    if args
+        .iter()
+        .any(|arg| arg == "--print-database" || arg == "-p")
+    {
+        print!("{}", get_default_database());
+        return;
+    }
+
+    // Like dircolors, the syntax defaults to the one of the user's shell.
+    let mut shell_syntax = match env::var("SHELL") {
+        Ok(shell) if shell.ends_with("csh") => ShellSyntax::CShell,
+        _ => ShellSyntax::Bourne,
+    };
+    let mut file: Option<&OsString> = None;
+
+    for arg in args {
+        match arg.to_str().unwrap_or_default() {
+            "--dircolors" => {}
+            "-b" | "--sh" | "--bourne-shell" => shell_syntax = ShellSyntax::Bourne,
+            "-c" | "--csh" | "--c-shell" => shell_syntax = ShellSyntax::CShell,
+            _ => file = Some(arg),
+        }
+    }
+
+    let result = read_database(file).and_then(|database| {
+        convert_database_to_ls_colors(
+            &database,
+            &env::var("TERM").unwrap_or_default(),
+            &env::var("COLORTERM").unwrap_or_default(),
+        )
+        .map_err(|error| match file {
+            Some(file) => format!(
+                "{}:{}",
+                quote_name_before_colon(file),
+                error.trim_start_matches("line ")
+            ),
+            None => format!("<internal>:{}", error.trim_start_matches("line ")),
+        })
+    });
+    match result {
+        Ok(ls_colors) => print_shell_code(&ls_colors, &shell_syntax),
+        Err(error_message) => report_error(&error_message, SERIOUS_PROBLEM),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Lines before the first TERM line apply to every terminal, consecutive TERM and COLORTERM
+    // lines form a single block matching any of them, and every block replaces the previous one.
+    const DATABASE: &str = "# global\n\
+                            DIR 01;34\n\
+                            TERM xterm*\n\
+                            TERM screen\n\
+                            LINK 01;36\n\
+                            .tar 01;31\n\
+                            TERM linux\n\
+                            *README 04\n\
+                            COLORTERM ?*\n\
+                            EXEC 01;32\n";
+
+    // The expected outputs are those of GNU dircolors for the same database.
+    #[test]
+    fn term_blocks_only_apply_to_the_matching_terminals() {
+        let expected = [
+            ("xterm-256color", "", "di=01;34:ln=01;36:*.tar=01;31:"),
+            ("screen", "", "di=01;34:ln=01;36:*.tar=01;31:"),
+            ("linux", "", "di=01;34:*README=04:"),
+            ("dumb", "", "di=01;34:"),
+            ("", "", "di=01;34:"),
+        ];
+        for (term, color_term, ls_colors) in expected {
+            assert_eq!(
+                convert_database_to_ls_colors(DATABASE, term, color_term),
+                Ok(String::from(ls_colors))
+            );
+        }
+    }
+
+    #[test]
+    fn colorterm_blocks_match_the_colorterm_variable() {
+        assert_eq!(
+            convert_database_to_ls_colors(DATABASE, "xterm-256color", "truecolor"),
+            Ok(String::from("di=01;34:ln=01;36:*.tar=01;31:ex=01;32:"))
+        );
+        assert_eq!(
+            convert_database_to_ls_colors(DATABASE, "dumb", "truecolor"),
+            Ok(String::from("di=01;34:ex=01;32:"))
+        );
+    }
+
+    #[test]
+    fn keywords_are_only_checked_in_the_matching_blocks() {
+        let database = "TERM xterm\nBOGUS 1\n";
+        assert_eq!(
+            convert_database_to_ls_colors(database, "xterm", ""),
+            Err(String::from("line 2: unrecognized keyword BOGUS"))
+        );
+        assert_eq!(
+            convert_database_to_ls_colors(database, "linux", ""),
+            Ok(String::new())
+        );
+        // A line without a value is invalid wherever it is.
+        assert_eq!(
+            convert_database_to_ls_colors("TERM xterm\nDIR\n", "linux", ""),
+            Err(String::from("line 2: invalid line; missing second token"))
+        );
+    }
+
+    #[test]
+    fn keywords_are_case_insensitive_and_comments_are_ignored() {
+        assert_eq!(
+            convert_database_to_ls_colors("dir 01;34 # comment\n  EXEC\t01;32\n", "", ""),
+            Ok(String::from("di=01;34:ex=01;32:"))
+        );
+    }
+
+    #[test]
+    fn the_default_database_converts_to_the_default_colors() {
+        assert_eq!(
+            convert_database_to_ls_colors(&get_default_database(), "", ""),
+            Ok(format!("{}:", DEFAULT_COLORS.trim_end_matches(':')))
+        );
+    }
+}
@@ -1,13 +1,18 @@
+#![allow(clippy::bool_comparison, clippy::needless_return)]
+
 use crate::*;
 use chrono::{DateTime, Local};
-use colored::{ColoredString, Colorize};
-use std::fs::{read_dir, ReadDir};
-use std::os::unix::fs::PermissionsExt;
+use colored::Colorize;
+use std::ffi::{OsStr, OsString};
+use std::fs::{read_dir, DirEntry, ReadDir};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
 use std::path::{Path, PathBuf};
 use xattr;
 
 // Checks whether or not the given file has extended attributes.
 // See https://en.wikipedia.org/wiki/Extended_file_attributes for more info.
+#[allow(clippy::needless_bool)]
 pub fn check_extended_attributes(path: &Path) -> bool {
     match xattr::list(path) {
         Ok(attributes) => {
@@ -17,52 +22,148 @@ pub fn check_extended_attributes(path: &Path) -> bool {
                 return true;
             }
         }
+        // File systems without extended attributes are not an error.
+        Err(err) if err.raw_os_error() == Some(libc::ENOTSUP) => false,
         Err(err) => {
-            println!("Error: {}", err);
+            report_error(
+                &format!(
+                    "{}: {}",
+                    quote_name_before_colon(path.as_os_str()),
+                    describe_io_error(&err)
+                ),
+                MINOR_PROBLEM,
+            );
             return false;
         }
     }
 }
 
 // Dot files are left out by default, meaning if the include_dot_files variable is set to true in
-// the Parameters struct, they have to be "manually" included again.
-fn insert_dot_files_in_vector(files: &mut Vec<File>) {
-    let current_folder = Path::new(".");
-    let parent_folder = Path::new("..");
-
-    let current_metadata = current_folder.metadata().unwrap();
-    let parent_metadata = parent_folder.metadata().unwrap();
-
-    files.push(File::new(
-        ".".to_string(),
-        current_metadata,
-        check_extended_attributes(current_folder),
-    ));
-    files.push(File::new(
-        "..".to_string(),
-        parent_metadata,
-        check_extended_attributes(parent_folder),
-    ));
+// the Parameters struct, they have to be "manually" included again. They describe the listed
+// directory and its parent.
+fn insert_dot_files_in_vector(directory: &Path, files: &mut Vec<File>, parameters: &Parameters) {
+    for name in [".", ".."] {
+        let path = directory.join(name);
+
+        match path.metadata() {
+            Ok(metadata) => files.push(File::new(
+                OsString::from(name),
+                &path,
+                metadata,
+                parameters.dereference == DereferenceMode::Always,
+            )),
+            Err(error) => {
+                report_error(
+                    &format!(
+                        "cannot access {}: {}",
+                        quote_name_in_message(path.as_os_str()),
+                        describe_io_error(&error)
+                    ),
+                    MINOR_PROBLEM,
+                );
+                files.push(File::placeholder(OsString::from(name), &path, None));
+            }
+        }
+    }
+}
+
+// Directories with fewer entries than this are always read by a single thread, as starting the
+// threads would cost more than they save.
+const PARALLEL_READING_THRESHOLD: usize = 128;
+
+// Creates the File object of a directory entry. The entry is only stat'ed if the output format
+// needs more than the type given by the directory itself. Entries that can't be stat'ed (e.g. if
+// they were deleted in the meantime) are reported and listed with a placeholder.
+fn create_file_from_entry(entry: &DirEntry, needs_metadata: bool, parameters: &Parameters) -> File {
+    if needs_metadata == false {
+        if let Ok(file_type) = entry.file_type() {
+            return File::from_file_type(get_path_name(&entry.path()), &entry.path(), file_type);
+        }
+    }
+
+    match entry.metadata() {
+        Ok(metadata) => File::new(
+            get_path_name(&entry.path()),
+            &entry.path(),
+            metadata,
+            parameters.dereference == DereferenceMode::Always,
+        ),
+        Err(error) => {
+            report_error(
+                &format!(
+                    "cannot access {}: {}",
+                    quote_name_in_message(entry.path().as_os_str()),
+                    describe_io_error(&error)
+                ),
+                MINOR_PROBLEM,
+            );
+            File::placeholder(
+                get_path_name(&entry.path()),
+                &entry.path(),
+                entry.file_type().ok(),
+            )
+        }
+    }
+}
+
+// Creates the File objects of the entries using the threads of the --threads parameter, each one
+// taking care of a contiguous chunk so that the order of the entries is kept.
+fn create_files_in_parallel(entries: Vec<DirEntry>, parameters: &Parameters) -> Vec<File> {
+    let chunk_size = entries.len().div_ceil(parameters.threads);
+    let needs_metadata = needs_metadata(parameters);
+    let mut entries = entries.into_iter();
+    let mut tasks: Vec<Task<Vec<File>>> = Vec::new();
+
+    loop {
+        let chunk: Vec<DirEntry> = entries.by_ref().take(chunk_size).collect();
+        if chunk.is_empty() {
+            break;
+        }
+        tasks.push(Box::new(move |parameters: &Parameters| {
+            chunk
+                .iter()
+                .map(|entry| create_file_from_entry(entry, needs_metadata, parameters))
+                .collect()
+        }));
+    }
+    run_in_parallel(tasks, parameters).concat()
 }
 
 // Assembles the vector returned in create_files_vector() by filling each File object with the
-// given metadata.
-fn insert_path_in_vector(paths: ReadDir, files: &mut Vec<File>, parameters: &Parameters) {
+// given metadata. Files hidden by the -a, -A and -I parameters are left out. Errors while reading
+// the directory are reported, the rest of it being listed anyway.
+fn insert_path_in_vector(
+    directory: &Path,
+    paths: ReadDir,
+    files: &mut Vec<File>,
+    parameters: &Parameters,
+) {
     if parameters.include_dot_files == true {
-        insert_dot_files_in_vector(files);
+        insert_dot_files_in_vector(directory, files, parameters);
     }
 
+    let mut entries: Vec<DirEntry> = Vec::new();
     for path in paths {
         match path {
-            Ok(path) => {
-                let metadata = path.metadata().unwrap();
-                files.push(File::new(
-                    get_path_name(&path.path()),
-                    metadata,
-                    check_extended_attributes(&path.path()),
-                ));
-            }
-            Err(error_message) => println!("{}", error_message),
+            Ok(path) if !is_visible(&get_path_name(&path.path()), parameters) => {}
+            Ok(path) => entries.push(path),
+            Err(error) => report_error(
+                &format!(
+                    "reading directory {}: {}",
+                    quote_name_in_message(directory.as_os_str()),
+                    describe_io_error(&error)
+                ),
+                MINOR_PROBLEM,
+            ),
+        }
+    }
+
+    if parameters.threads > 1 && entries.len() >= PARALLEL_READING_THRESHOLD {
+        files.append(&mut create_files_in_parallel(entries, parameters));
+    } else {
+        let needs_metadata = needs_metadata(parameters);
+        for entry in &entries {
+            files.push(create_file_from_entry(entry, needs_metadata, parameters));
         }
     }
 }
@@ -84,28 +185,12 @@ fn file_rank_redirect(files: &mut Vec<File>, parameters: &Parameters) {
     }
 }
 
-// Redirects to the right ranking function, based on the given parameters.
-fn path_buf_rank_redirect(pathbufs: &mut Vec<PathBuf>, parameters: &Parameters) {
-    if parameters.reverse_order == true {
-        if parameters.last_modified_order == true {
-            reverse_rank_path_bufs_by_last_modified_date(pathbufs);
-        } else {
-            reverse_alphabetically_rank_path_bufs(pathbufs);
-        }
-    } else {
-        if parameters.last_modified_order == true {
-            rank_path_bufs_by_last_modified_date(pathbufs);
-        } else {
-            alphabetically_rank_path_bufs(pathbufs);
-        }
-    }
-}
-
 // Returns a simple vector of File objects based on the given path.
-fn create_files_vector(paths: ReadDir, parameters: &Parameters) -> Vec<File> {
+fn create_files_vector(directory: &Path, paths: ReadDir, parameters: &Parameters) -> Vec<File> {
     let mut files: Vec<File> = Vec::new();
 
-    insert_path_in_vector(paths, &mut files, parameters);
+    insert_path_in_vector(directory, paths, &mut files, parameters);
+    fill_security_contexts(&mut files, parameters);
     file_rank_redirect(&mut files, parameters);
 
     return files;
@@ -114,26 +199,30 @@ fn create_files_vector(paths: ReadDir, parameters: &Parameters) -> Vec<File> {
 // If there is only one argument, this is the function being used.
 // Redirects to long_format_print or simple_print depending on whether or not the user wants
 // a detailed output or the simple default output.
-fn handle_single_arguments(target_path: &str, parameters: &Parameters) {
+fn handle_single_arguments(target_path: &OsStr, git_cache: &mut GitCache, parameters: &Parameters) {
     if is_file(target_path) {
-        println!("{}", target_path);
+        print_bytes(&quote_name(target_path, parameters));
+        println!();
     } else {
-        match one_argument(target_path, parameters) {
+        match one_argument(Path::new(target_path), parameters) {
             Ok(files) => {
                 if parameters.long_format == true {
-                    long_format_print(files, parameters, false)
+                    long_format_print(files, git_cache, parameters, false)
                 } else {
                     simple_print(files, parameters)
                 }
             }
-            Err(error_message) => println!("{}", error_message),
+            Err(error_message) => report_error(&error_message, SERIOUS_PROBLEM),
         }
     }
 }
 
 // Checks the file's type, used for the long format printing.
 fn file_type(file: &File) -> String {
-    if file.is_dir == true {
+    // Only the placeholders of files whose type is unknown have no type bits.
+    if file.file_mode.mode() & 0o170000 == 0 {
+        String::from("?")
+    } else if file.is_dir == true {
         String::from("d")
     } else if file.is_symbolic_link == true {
         String::from("l")
@@ -153,7 +242,7 @@ fn permission_bits(mode: u32, read: u32, write: u32, execute: u32) -> String {
 
 // Finds the length of the highest number of hard links the File vector has, which is used to get
 // the right layout for the command's output for long format printing.
-fn get_longest_number_of_links(files: &Vec<File>) -> usize {
+pub fn get_longest_number_of_links<'a>(files: impl IntoIterator<Item = &'a File>) -> usize {
     let mut longest_number = 1;
 
     for i in files {
@@ -166,7 +255,7 @@ fn get_longest_number_of_links(files: &Vec<File>) -> usize {
 
 // Finds the length of the highest number for a file's size that the File vector has, which is used to get
 // the right layout for the command's output for long format printing.
-fn get_longest_file_size(files: &Vec<File>) -> usize {
+pub fn get_longest_file_size<'a>(files: impl IntoIterator<Item = &'a File>) -> usize {
     let mut longest_file_size = 1;
 
     for i in files {
@@ -191,12 +280,12 @@ fn get_total_number_of_blocks(files: &Vec<File>) -> u64 {
 fn print_permissions(file: &File) {
     let mode = file.file_mode.mode();
 
-    print!("{}", file_type(&file));
+    print!("{}", file_type(file));
     print!("{}", permission_bits(mode, 0o400, 0o200, 0o100));
     print!("{}", permission_bits(mode, 0o040, 0o020, 0o010));
     print!("{}", permission_bits(mode, 0o004, 0o002, 0o001));
 
-    if file.extended_attributes == true {
+    if check_extended_attributes(&file.full_path) == true {
         print!("@ ");
     } else {
         print!("  ");
@@ -215,30 +304,152 @@ fn print_spacing_difference(number_one: usize, number_two: usize) {
 
 // Prints the file name, as well as the file it's pointing to if it's a symbolic link. Used for
 // long format printing.
-fn print_file_name_long_format(file: &File) {
-    print!("{}", color_print(&file));
+pub fn print_file_name_long_format(file: &File, parameters: &Parameters) {
+    print_bytes(&color_print(file, parameters));
     if file.is_symbolic_link == true {
-        print!(" -> {}", get_symbolic_link(file));
+        print!(" -> ");
+        print_bytes(&color_print_symbolic_link_target(file, parameters));
+        print!("{}", get_symbolic_link_target_indicator(file, parameters));
+    } else {
+        print!("{}", get_file_indicator(file, parameters));
+    }
+    if parameters.capabilities == true {
+        if let Some(capabilities) = get_capabilities(file) {
+            print!(" {}", capabilities);
+        }
     }
     println!();
 }
 
-// Prints the file's last modification date in the following format: May 30 18:22.
-// Used for long format printing.
-fn print_date_long_format(file: &File) {
-    let datetime: DateTime<Local> = file.last_modified.into();
+// Prints the file's git status (staged and unstaged changes), like "exa --git" does. Used for long
+// format printing when the --git parameter is included.
+fn print_git_status_long_format(file: &File, git_cache: &mut GitCache, parameters: &Parameters) {
+    let status = git_cache.status(file);
+
+    if parameters.colors.is_none() {
+        print!("{} ", status);
+        return;
+    }
+    for character in status.chars() {
+        let character = character.to_string();
+        let colored_character = match character.as_str() {
+            "N" => character.green(),
+            "M" => character.blue(),
+            "D" => character.red(),
+            "T" => character.yellow(),
+            "U" => character.red().bold(),
+            "I" => character.dimmed(),
+            _ => character.normal(),
+        };
+        print!("{}", colored_character);
+    }
+    print!(" ");
+}
+
+// Prints the file's last modification date in the following format: May 30 18:22, or its birth
+// date with --time=birth ("?" if the file system doesn't record it). Used for long format printing.
+fn print_date_long_format(file: &File, parameters: &Parameters) {
+    let time = if parameters.birth_time == true {
+        get_extended_metadata(&file.full_path, !file.is_symbolic_link)
+            .and_then(|metadata| metadata.birth_time)
+    } else {
+        file.last_modified
+    };
+    let time = match time {
+        Some(time) => time,
+        None => {
+            print!("{:>12} ", "?");
+            return;
+        }
+    };
+
+    let datetime: DateTime<Local> = time.into();
     let formatted = datetime.format("%b %e %H:%M").to_string();
     print!("{} ", formatted);
 }
 
-// Called when the -l parameter is included in the command.
-pub fn long_format_print(mut files: Vec<File>, parameters: &Parameters, single_files: bool) {
-    // Remove all the files where the name starts with a dot, if the -a parameter was not included.
-    if parameters.include_dot_files == false {
-        files.retain(|file| !file.path_name.starts_with('.'));
+// Prints the columns of a file that couldn't be stat'ed, like coreutils does: everything but its
+// type (when known) is replaced by question marks.
+fn print_placeholder_columns(
+    file: &File,
+    longest_number: usize,
+    longest_security_context: usize,
+    longest_file_size: usize,
+    parameters: &Parameters,
+) {
+    print!("{}?????????  ", file_type(file));
+    print_spacing_difference(longest_number, 1);
+    print!("? ?  ?  ");
+    if parameters.security_context == true {
+        print!("{:<width$}  ", "?", width = longest_security_context);
+    }
+    print_spacing_difference(longest_file_size, 1);
+    print!("? {:>12} ", "?");
+    if parameters.git_status == true {
+        print!("-- ");
     }
+    if parameters.attributes == true {
+        print!("{:<22} ", "?");
+    }
+}
+
+// Prints all the columns of the long format that come before the file's name, the widths being
+// computed beforehand for all the listed files so that the columns are aligned.
+pub fn print_long_format_columns(
+    file: &File,
+    longest_number: usize,
+    longest_security_context: usize,
+    longest_file_size: usize,
+    git_cache: &mut GitCache,
+    parameters: &Parameters,
+) {
+    if file.is_accessible == false {
+        print_placeholder_columns(
+            file,
+            longest_number,
+            longest_security_context,
+            longest_file_size,
+            parameters,
+        );
+        return;
+    }
+
+    print_permissions(file);
+    print_spacing_difference(longest_number, file.number_of_links.to_string().len());
+
+    print!("{} ", file.number_of_links);
+    print!("{}  ", get_username(file.owner_id));
+    print!("{}  ", get_group(file.group_id));
+    if parameters.security_context == true {
+        let security_context = get_security_context_name(file);
+        print!("{}", security_context);
+        print_spacing_difference(
+            longest_security_context,
+            get_display_width(&security_context),
+        );
+        print!("  ");
+    }
+
+    print_spacing_difference(longest_file_size, file.number_of_bytes.to_string().len());
+    print!("{} ", file.number_of_bytes);
+    print_date_long_format(file, parameters);
+    if parameters.git_status == true {
+        print_git_status_long_format(file, git_cache, parameters);
+    }
+    if parameters.attributes == true {
+        print!("{:<22} ", get_attribute_flags(file));
+    }
+}
 
+// Called when the -l parameter is included in the command.
+pub fn long_format_print(
+    files: Vec<File>,
+    git_cache: &mut GitCache,
+    parameters: &Parameters,
+    single_files: bool,
+) {
     let longest_number = get_longest_number_of_links(&files);
+    let longest_security_context = get_longest_security_context(&files);
     let longest_file_size = get_longest_file_size(&files);
 
     if single_files == false {
@@ -246,38 +457,41 @@ pub fn long_format_print(mut files: Vec<File>, parameters: &Parameters, single_f
     }
 
     for file in files {
-        print_permissions(&file);
-        print_spacing_difference(longest_number, file.number_of_links.to_string().len());
-
-        print!("{} ", file.number_of_links);
-        print!("{}  ", file.owner_name);
-        print!("{}  ", file.group_name);
-
-        print_spacing_difference(longest_file_size, file.number_of_bytes.to_string().len());
-        print!("{} ", file.number_of_bytes);
-        print_date_long_format(&file);
-        print_file_name_long_format(&file);
+        print_long_format_columns(
+            &file,
+            longest_number,
+            longest_security_context,
+            longest_file_size,
+            git_cache,
+            parameters,
+        );
+        print_file_name_long_format(&file, parameters);
     }
 }
 
 // Handle commands with multiple files or directories to list, but without any parameter.
-fn handle_folders(args: Vec<String>, multiple_arguments: bool, parameters: &Parameters) {
+fn handle_folders(
+    args: Vec<OsString>,
+    multiple_arguments: bool,
+    git_cache: &mut GitCache,
+    parameters: &Parameters,
+) {
     let counter = 0;
 
     // if args[0].chars().nth(0).unwrap() != '-' {
     if args.len() > 1 || multiple_arguments == true {
-        handle_multiple_arguments(args, parameters);
+        handle_multiple_arguments(args, git_cache, parameters);
     } else {
-        handle_single_arguments(&args[counter], parameters);
+        handle_single_arguments(&args[counter], git_cache, parameters);
     }
     // }
 }
 
 // Redirects to the right type of printing, depending on whether or not the -l parameter was
 // included.
-fn print_format_redirect(files: Vec<File>, parameters: &Parameters) {
+fn print_format_redirect(files: Vec<File>, git_cache: &mut GitCache, parameters: &Parameters) {
     if parameters.long_format == true {
-        long_format_print(files, parameters, false);
+        long_format_print(files, git_cache, parameters, false);
     } else {
         simple_print(files, parameters);
     }
@@ -285,44 +499,228 @@ fn print_format_redirect(files: Vec<File>, parameters: &Parameters) {
 
 // Iterates through all the command's arguments to print them one by one according to the
 // formatting of the "ls" command.
-fn handle_multiple_arguments(args: Vec<String>, parameters: &Parameters) {
+fn handle_multiple_arguments(
+    args: Vec<OsString>,
+    git_cache: &mut GitCache,
+    parameters: &Parameters,
+) {
     let mut counter = 0;
 
     while counter != args.len() {
         if is_file(&args[counter]) {
-            println!("{}", &args[counter]);
+            print_bytes(&quote_name(&args[counter], parameters));
+            println!();
             if counter != args.len() - 1 {
                 println!();
             }
         } else {
-            match one_argument(&args[counter], parameters) {
+            match one_argument(Path::new(&args[counter]), parameters) {
                 Ok(files) => {
-                    print!("{}:", &args[counter]);
-                    if !files.is_empty() {
-                        println!();
-                    }
-                    print_format_redirect(files, parameters);
+                    print_bytes(&quote_name(&args[counter], parameters));
+                    println!(":");
+                    print_format_redirect(files, git_cache, parameters);
                     if counter != args.len() - 1 {
                         println!();
                     }
                 }
-                Err(error_message) => println!("{}", error_message),
+                Err(error_message) => report_error(&error_message, SERIOUS_PROBLEM),
             }
         }
         counter += 1;
     }
 }
 
+// Parses the value of the --max-depth (or --level) parameter, exiting if it is not a number.
+fn parse_max_depth(value: &str) -> usize {
+    match value.parse::<usize>() {
+        Ok(max_depth) => max_depth,
+        Err(_) => report_invalid_usage(&format!("invalid maximum depth: '{}'", value)),
+    }
+}
+
+// Parses the value of the --threads parameter, 0 meaning one thread per CPU. Exits if the value is
+// not a number.
+fn parse_threads(value: &str) -> usize {
+    match value.parse::<usize>() {
+        Ok(0) => get_number_of_cpus(),
+        Ok(threads) => threads,
+        Err(_) => report_invalid_usage(&format!("invalid number of threads: '{}'", value)),
+    }
+}
+
+// Parses the value of the --quoting-style parameter, exiting if it is not a known style.
+fn parse_quoting_style_parameter(value: &str) -> QuotingStyle {
+    match parse_quoting_style(value) {
+        Some(quoting_style) => quoting_style,
+        None => report_invalid_usage(&format!(
+            "invalid argument '{}' for '--quoting-style'",
+            value
+        )),
+    }
+}
+
+// Long parameters (starting with "--") are matched as a whole, instead of letter by letter.
+fn parse_long_parameter(parameter: &str, parameters: &mut Parameters) {
+    if let Some(value) = parameter.strip_prefix("--threads=") {
+        parameters.threads = parse_threads(value);
+        return;
+    }
+
+    if let Some(value) = parameter
+        .strip_prefix("--max-depth=")
+        .or_else(|| parameter.strip_prefix("--level="))
+    {
+        parameters.max_depth = Some(parse_max_depth(value));
+        return;
+    }
+
+    if let Some(value) = parameter.strip_prefix("--quoting-style=") {
+        parameters.quoting_style = parse_quoting_style_parameter(value);
+        return;
+    }
+
+    match parameter {
+        "--git" => parameters.git_status = true,
+        "--classify" | "--indicator-style=classify" => {
+            parameters.indicator_style = IndicatorStyle::Classify
+        }
+        "--file-type" | "--indicator-style=file-type" => {
+            parameters.indicator_style = IndicatorStyle::FileType
+        }
+        "--indicator-style=slash" => parameters.indicator_style = IndicatorStyle::Slash,
+        "--tree" => parameters.tree = true,
+        "--parallel" => parameters.threads = get_number_of_cpus(),
+        "--attrs" => parameters.attributes = true,
+        "--context" => parameters.security_context = true,
+        "--caps" => parameters.capabilities = true,
+        "--time=birth" | "--time=creation" => parameters.birth_time = true,
+        "--time=mtime" | "--time=modification" => parameters.birth_time = false,
+        "--all" => parameters.include_dot_files = true,
+        "--almost-all" => parameters.include_almost_all_dot_files = true,
+        "--dereference" => parameters.dereference = DereferenceMode::Always,
+        "--dereference-command-line" => parameters.dereference = DereferenceMode::CommandLine,
+        "--dereference-command-line-symlink-to-dir" => {
+            parameters.dereference = DereferenceMode::CommandLineSymlinkToDir
+        }
+        "--indicator-style=none" => parameters.indicator_style = IndicatorStyle::None,
+        "--literal" => parameters.quoting_style = QuotingStyle::Literal,
+        "--quote-name" => parameters.quoting_style = QuotingStyle::C,
+        "--escape" => parameters.quoting_style = QuotingStyle::Escape,
+        "--hide-control-chars" => parameters.hide_control_characters = true,
+        "--show-control-chars" => parameters.hide_control_characters = false,
+        "--color" | "--colour" | "--color=always" | "--color=yes" | "--color=force" => {
+            parameters.colors = get_color_database(&ColorMode::Always)
+        }
+        "--color=auto" | "--color=tty" | "--color=if-tty" => {
+            parameters.colors = get_color_database(&ColorMode::Auto)
+        }
+        "--color=never" | "--color=no" | "--color=none" => {
+            parameters.colors = get_color_database(&ColorMode::Never)
+        }
+        _ => report_invalid_usage(&format!("unrecognized option '{}'", parameter)),
+    }
+}
+
+// Collects the patterns of the -I parameter ("-I PATTERN", "-IPATTERN" or "--ignore=PATTERN"),
+// removing them from the arguments so that they are not mistaken for files or other parameters.
+// The arguments after "--" are left untouched.
+fn extract_ignore_patterns(args: &mut Vec<OsString>, parameters: &mut Parameters) {
+    let mut counter = 0;
+
+    while counter < args.len() {
+        let arg = args[counter].as_bytes();
+        if arg == b"--" {
+            return;
+        } else if arg == b"-I" && counter + 1 < args.len() {
+            parameters.ignore_patterns.push(args.remove(counter + 1));
+            args.remove(counter);
+        } else if let Some(pattern) = arg.strip_prefix(b"--ignore=") {
+            parameters
+                .ignore_patterns
+                .push(OsStr::from_bytes(pattern).to_os_string());
+            args.remove(counter);
+        } else if arg.starts_with(b"-I") && !arg.starts_with(b"--") {
+            parameters
+                .ignore_patterns
+                .push(OsStr::from_bytes(&arg[2..]).to_os_string());
+            args.remove(counter);
+        } else {
+            counter += 1;
+        }
+    }
+}
+
+// The letters accepted in short parameters (-I is handled by extract_ignore_patterns()).
+const SHORT_PARAMETERS: &str = "aAlrtRpFHLZNQbq";
+
+// Reports a parameter that isn't valid UTF-8, as none of the valid ones contain such bytes. Like
+// getopt(), this names the first invalid letter of short parameters, and the whole of long ones.
+// The bytes that aren't valid UTF-8 are written in octal, e.g. "\351".
+fn report_non_utf8_parameter(parameter: &OsStr) -> ! {
+    let escape =
+        |bytes: &[u8]| -> String { bytes.iter().map(|byte| format!("\\{:03o}", byte)).collect() };
+
+    if !parameter.as_bytes().starts_with(b"--") {
+        for chunk in parameter.as_bytes()[1..].utf8_chunks() {
+            if let Some(letter) = chunk
+                .valid()
+                .chars()
+                .find(|letter| !SHORT_PARAMETERS.contains(*letter))
+            {
+                report_invalid_usage(&format!("invalid option -- '{}'", letter));
+            }
+            if let Some(byte) = chunk.invalid().first() {
+                report_invalid_usage(&format!("invalid option -- '{}'", escape(&[*byte])));
+            }
+        }
+    }
+    let escaped_parameter: String = parameter
+        .as_bytes()
+        .utf8_chunks()
+        .map(|chunk| format!("{}{}", chunk.valid(), escape(chunk.invalid())))
+        .collect();
+    report_invalid_usage(&format!("unrecognized option '{}'", escaped_parameter));
+}
+
 // Looks for the command's parameters and saves them in struct.
-fn parse_parameters(args: &mut Vec<String>) -> Parameters {
+fn parse_parameters(args: &mut Vec<OsString>) -> Parameters {
     let mut parameters = Parameters::new();
 
-    for i in &mut *args {
-        if i.starts_with('-') {
+    extract_ignore_patterns(args, &mut parameters);
+
+    // Like coreutils, "--" marks the end of the parameters: everything after it is a file, even if
+    // it starts with a "-".
+    let mut files_after_separator = match args.iter().position(|arg| arg == "--") {
+        Some(separator) => args.split_off(separator).split_off(1),
+        None => Vec::new(),
+    };
+
+    // Parameters are always valid UTF-8, unlike file names.
+    for arg in args.iter() {
+        let i = match arg.to_str() {
+            Some(i) => i,
+            None if arg.as_bytes().starts_with(b"-") => report_non_utf8_parameter(arg),
+            None => continue,
+        };
+        if i.starts_with("--") {
+            parse_long_parameter(i, &mut parameters);
+        } else if i.starts_with('-') {
+            if let Some(letter) = i
+                .chars()
+                .skip(1)
+                .find(|letter| !SHORT_PARAMETERS.contains(*letter))
+            {
+                report_invalid_usage(&format!("invalid option -- '{}'", letter));
+            }
+
             if i.contains("a") {
                 parameters.include_dot_files = true;
             }
 
+            if i.contains("A") {
+                parameters.include_almost_all_dot_files = true;
+            }
+
             if i.contains("l") {
                 parameters.long_format = true;
             }
@@ -338,19 +736,71 @@ fn parse_parameters(args: &mut Vec<String>) -> Parameters {
             if i.contains("R") {
                 parameters.recursive_listing = true;
             }
+
+            // Like the long parameters, the last of -p and -F wins, even in "-Fp".
+            match i
+                .chars()
+                .rev()
+                .find(|letter| *letter == 'p' || *letter == 'F')
+            {
+                Some('p') => parameters.indicator_style = IndicatorStyle::Slash,
+                Some(_) => parameters.indicator_style = IndicatorStyle::Classify,
+                None => {}
+            }
+
+            if i.contains("H") {
+                parameters.dereference = DereferenceMode::CommandLine;
+            }
+
+            if i.contains("L") {
+                parameters.dereference = DereferenceMode::Always;
+            }
+
+            if i.contains("Z") {
+                parameters.security_context = true;
+            }
+
+            if i.contains("N") {
+                parameters.quoting_style = QuotingStyle::Literal;
+            }
+
+            if i.contains("Q") {
+                parameters.quoting_style = QuotingStyle::C;
+            }
+
+            if i.contains("b") {
+                parameters.quoting_style = QuotingStyle::Escape;
+            }
+
+            if i.contains("q") {
+                parameters.hide_control_characters = true;
+            }
         }
     }
 
-    args.retain(|s| !s.starts_with('-'));
+    // Like coreutils, symbolic links given as arguments are shown themselves (instead of the
+    // directory they point to) in long format and with -F, unless specified otherwise.
+    if parameters.dereference == DereferenceMode::CommandLineSymlinkToDir
+        && !args
+            .iter()
+            .any(|arg| arg == "--dereference-command-line-symlink-to-dir")
+        && (parameters.long_format == true
+            || parameters.indicator_style == IndicatorStyle::Classify)
+    {
+        parameters.dereference = DereferenceMode::Never;
+    }
+
+    args.retain(|s| !s.as_bytes().starts_with(b"-"));
+    args.append(&mut files_after_separator);
     if args.is_empty() {
-        args.push("./".to_string());
+        args.push(OsString::from("./"));
     }
 
     return parameters;
 }
 
 // Orders the arguments based on the given command parameters.
-fn check_parameters(parameters: &Parameters, mut args: Vec<String>) -> Vec<String> {
+fn check_parameters(parameters: &Parameters, mut args: Vec<OsString>) -> Vec<OsString> {
     if parameters.reverse_order == true {
         if parameters.last_modified_order == true {
             reverse_rank_path_by_last_modified_date(&mut args);
@@ -364,262 +814,307 @@ fn check_parameters(parameters: &Parameters, mut args: Vec<String>) -> Vec<Strin
     args
 }
 
-// Navigates the subfolders recursively and collects them in a vector for the -R command output.
-fn directory_traversal(path: &PathBuf, parameters: &Parameters) -> Vec<PathBuf> {
-    let mut directories: Vec<PathBuf> = Vec::new();
-    // let directory = read_dir(path).unwrap();
-    let mut directory: Vec<PathBuf> = read_dir(path)
-        .unwrap()
-        .map(|res| res.map(|e| e.path()))
-        .collect::<Result<Vec<_>, _>>()
-        .unwrap();
-
-    path_buf_rank_redirect(&mut directory, parameters);
+// Returns the device and inode numbers identifying a directory, following symbolic links.
+pub fn get_directory_identifier(path: &Path) -> Option<(u64, u64)> {
+    path.metadata()
+        .ok()
+        .map(|metadata| (metadata.dev(), metadata.ino()))
+}
 
-    for entry in directory {
-        if entry.is_dir() && !(get_path_name(&entry).starts_with('.')) {
-            // println!("{}", entry.file_name().unwrap().to_str().unwrap());
-            directories.push(entry.clone());
-            directories.append(&mut directory_traversal(&entry, &parameters));
+// Lists a directory and then, depth-first, its subfolders for the -R command output. Each
+// directory is read once and printed as soon as it is read, only keeping the paths of the
+// subfolders left to visit, so nothing has to be collected beforehand.
+// The "ancestors" vector holds the identifiers of the directories being traversed, so that
+// directory cycles (bind mounts or symbolic links with -L) are detected instead of followed.
+// "depth" is the number of levels the directory is below the command's argument, which stops the
+// traversal once it is deeper than the --max-depth parameter.
+#[allow(clippy::too_many_arguments)]
+fn directory_traversal(
+    path: &Path,
+    listing: Result<Vec<File>, String>,
+    print_header: bool,
+    is_first_directory: &mut bool,
+    ancestors: &mut Vec<(u64, u64)>,
+    depth: usize,
+    git_cache: &mut GitCache,
+    parameters: &Parameters,
+) {
+    let files = match listing {
+        Ok(files) => files,
+        Err(error_message) => {
+            // Only the argument itself is given on the command line.
+            let status = if depth == 0 {
+                SERIOUS_PROBLEM
+            } else {
+                MINOR_PROBLEM
+            };
+            report_error(&error_message, status);
+            return;
         }
+    };
+
+    // Symbolic links to directories are only followed with the -L parameter, in which case the
+    // File objects describe their target. The recursion follows the same visibility rules as the
+    // listing, but never goes back up into "." or "..".
+    let subfolders: Vec<PathBuf> = files
+        .iter()
+        .filter(|file| file.is_dir == true && file.path_name != "." && file.path_name != "..")
+        .map(|file| file.full_path.clone())
+        .collect();
+
+    if *is_first_directory == false {
+        println!();
     }
-    return directories;
-}
+    *is_first_directory = false;
+    if print_header == true {
+        print_bytes(&quote_name(path.as_os_str(), parameters));
+        println!(":");
+    }
+    print_format_redirect(files, git_cache, parameters);
 
-// If only one folder was mentioned as argument, include the files in it. Requires a dedicated
-// function as the output is different for these files, than for the others that are part of the
-// directories that were recursively collected.
-fn include_root_files(single_files: &mut Vec<File>, folder: &String, parameters: &Parameters) {
-    match one_argument(folder, &parameters) {
-        Ok(mut files) => {
-            files.append(single_files);
-            if parameters.long_format == true {
-                long_format_print(files, parameters, false);
-            } else {
-                simple_print(files, &parameters);
+    if parameters
+        .max_depth
+        .is_some_and(|max_depth| depth >= max_depth)
+    {
+        return;
+    }
+
+    // The subfolders are read in batches, one per thread of the --threads parameter, but printed
+    // (and recursed into) one by one in their original order.
+    for batch in subfolders.chunks(parameters.threads) {
+        let identifiers: Vec<Option<(u64, u64)>> = batch
+            .iter()
+            .map(|subfolder| get_directory_identifier(subfolder))
+            .collect();
+        let is_cycle: Vec<bool> = identifiers
+            .iter()
+            .map(|identifier| identifier.is_some_and(|identifier| ancestors.contains(&identifier)))
+            .collect();
+        let listings = read_folders(batch, &is_cycle, parameters);
+
+        for ((subfolder, identifier), listing) in batch.iter().zip(identifiers).zip(listings) {
+            let listing = match listing {
+                Some((listing, errors)) => {
+                    // Reported now, after the output of the directories listed before this one.
+                    report_errors(errors);
+                    listing
+                }
+                None => {
+                    report_error(
+                        &format!(
+                            "{}: not listing already-listed directory",
+                            quote_name_before_colon(subfolder.as_os_str())
+                        ),
+                        SERIOUS_PROBLEM,
+                    );
+                    continue;
+                }
+            };
+
+            if let Some(identifier) = identifier {
+                ancestors.push(identifier);
+            }
+            directory_traversal(
+                subfolder,
+                listing,
+                true,
+                is_first_directory,
+                ancestors,
+                depth + 1,
+                git_cache,
+                parameters,
+            );
+            if identifier.is_some() {
+                ancestors.pop();
             }
         }
-        Err(error_message) => println!("{}", error_message),
     }
 }
 
-// Creates a vector of all subfolders of the command's mentioned folders as arguments.
-fn assemble_vectors(
-    args: &mut Vec<String>,
-    directories: &mut Vec<PathBuf>,
-    single_files: &mut Vec<File>,
+// The content of a folder read by read_folders(), along with the errors reported while reading it.
+type FolderListing = (Result<Vec<File>, String>, Vec<ReportedError>);
+
+// Reads the content of a batch of folders, in parallel if there is more than one. The folders
+// marked as skipped are not read and get None. The results keep the order of the folders, and
+// come with the errors reported while reading them, so that they are printed along with them.
+fn read_folders(
+    folders: &[PathBuf],
+    is_skipped: &[bool],
     parameters: &Parameters,
-) {
-    let number_of_arguments = args.len();
+) -> Vec<Option<FolderListing>> {
+    let tasks: Vec<Task<_>> = folders
+        .iter()
+        .zip(is_skipped)
+        .map(|(folder, is_skipped)| {
+            let folder = folder.clone();
+            let is_skipped = *is_skipped;
+            Box::new(move |parameters: &Parameters| {
+                if is_skipped == true {
+                    None
+                } else {
+                    Some(collect_errors(|| one_argument(&folder, parameters)))
+                }
+            }) as Task<_>
+        })
+        .collect();
 
-    for i in &mut *args {
-        let path = Path::new(&i);
-        if path.is_dir() {
-            if number_of_arguments > 1 {
-                directories.push(path.to_path_buf());
-            }
-            directories.append(&mut directory_traversal(&path.to_path_buf(), &parameters));
-        } else {
-            single_files.push(File::new(
-                get_path_name(&path.to_path_buf()),
-                path.metadata().unwrap(),
-                check_extended_attributes(&path),
-            ));
-        }
+    if folders.len() < 2 {
+        return tasks.into_iter().map(|task| task(parameters)).collect();
     }
+    run_in_parallel(tasks, parameters)
 }
 
 // Called when the -R parameter is included.
-fn handle_recursivity(args: &mut Vec<String>, parameters: &Parameters) {
-    let mut directories: Vec<PathBuf> = Vec::new();
-    let mut single_files: Vec<File> = Vec::new();
-
+fn handle_recursivity(args: &mut Vec<OsString>, git_cache: &mut GitCache, parameters: &Parameters) {
     alphabetically_rank_strings(args);
 
     // Handles specific files mentioned as arguments in the command.
-    let do_single_files_not_exist = handle_single_files(args, parameters);
+    let do_single_files_not_exist = handle_single_files(args, git_cache, parameters);
     if do_single_files_not_exist == false && args.is_empty() == false {
         println!();
     }
 
-    assemble_vectors(args, &mut directories, &mut single_files, parameters);
-
-    // If there is only one argument that is a folder, print its content first before the recursive
-    // output.
-    if args.len() == 1 {
-        if do_single_files_not_exist == false {
-            println!("{}:", &args[0]);
-        }
-        include_root_files(&mut single_files, &args[0], parameters);
-        if !directories.is_empty() {
-            println!();
-        }
+    // The folders' names are printed before their content, unless there is only one argument.
+    let print_header = args.len() > 1 || do_single_files_not_exist == false;
+    let mut is_first_directory = true;
+
+    for argument in args.iter() {
+        let path = Path::new(argument);
+        let mut ancestors: Vec<(u64, u64)> = get_directory_identifier(path).into_iter().collect();
+        directory_traversal(
+            path,
+            one_argument(path, parameters),
+            print_header,
+            &mut is_first_directory,
+            &mut ancestors,
+            0,
+            git_cache,
+            parameters,
+        );
     }
-
-    // Print the contents of all the directories indexed by the directory_traversal function.
-    handle_folders(
-        convert_path_buf_vector_to_string_vector(&directories),
-        true,
-        parameters,
-    );
 }
 
 // Handles both commands with multiple arguments but without parameters, and vice-versa.
-pub fn handle_command(mut args: Vec<String>) {
-    // let mut args: Vec<String> = env::args().collect();
+pub fn handle_command(mut args: Vec<OsString>) {
+    // let mut args: Vec<OsString> = env::args_os().collect();
 
     args.remove(0);
 
+    if args
+        .iter()
+        .take_while(|arg| *arg != "--")
+        .any(|arg| arg == "--dircolors" || arg == "--print-database")
+    {
+        return handle_dircolors(&args);
+    }
+
     let parameters = parse_parameters(&mut args);
+    // The threads of the --threads parameter are shared by the whole command.
+    run_with_workers(&parameters, || handle_arguments(args, &parameters));
+}
+
+// Lists the command's arguments, once the parameters are parsed.
+fn handle_arguments(mut args: Vec<OsString>, parameters: &Parameters) {
     handle_unexisting_files(&mut args);
+    // Shared by the whole command, so that each repository is only loaded once.
+    let mut git_cache = GitCache::new();
+
+    if parameters.tree == true {
+        return handle_tree(&args, &mut git_cache, parameters);
+    }
 
     if parameters.recursive_listing == true {
-        return handle_recursivity(&mut args, &parameters);
+        return handle_recursivity(&mut args, &mut git_cache, parameters);
     }
 
-    let empty_single_files = handle_single_files(&mut args, &parameters);
+    let empty_single_files = handle_single_files(&mut args, &mut git_cache, parameters);
 
-    args = check_parameters(&parameters, args);
+    args = check_parameters(parameters, args);
 
     if !args.is_empty() {
         if empty_single_files == true {
-            handle_folders(args, false, &parameters);
+            handle_folders(args, false, &mut git_cache, parameters);
         } else {
             println!();
-            handle_folders(args, true, &parameters);
+            handle_folders(args, true, &mut git_cache, parameters);
         }
     }
 }
 
-// Directories and files are colored differently, which is why this function is needed.
-fn color_print(file: &File) -> ColoredString {
-    if file.is_dir == true {
-        return format!("{}", file.path_name).cyan().bold();
-    } else if file.is_symbolic_link == true {
-        return format!("{}", file.path_name).purple();
-    } else if is_executable(file) == true {
-        return format!("{}", file.path_name).red();
-    } else {
-        return format!("{}", file.path_name).white();
+// Directories and files are colored differently, which is why this function is needed. The colors
+// come from LS_COLORS (or DEFAULT_COLORS), and nothing is colored when colors are disabled.
+pub fn color_print(file: &File, parameters: &Parameters) -> Vec<u8> {
+    match &parameters.colors {
+        Some(colors) => colors.paint(
+            &quote_name(&file.path_name, parameters),
+            colors.file_sequence(file),
+        ),
+        None => quote_name(&file.path_name, parameters),
+    }
+}
+
+// Colors the target of a symbolic link according to the target's own type, or with the "mi"
+// color if it does not exist. Used for long format printing.
+fn color_print_symbolic_link_target(file: &File, parameters: &Parameters) -> Vec<u8> {
+    let target = get_symbolic_link(file);
+    let quoted_target = quote_name(&target, parameters);
+
+    match &parameters.colors {
+        Some(colors) => colors.paint(
+            &quoted_target,
+            colors.symbolic_link_target_sequence(file, &target),
+        ),
+        None => quoted_target,
     }
 }
 
 // The function used when all that is needed is to output the files, without information about
-// them.
-pub fn simple_print(mut files: Vec<File>, parameters: &Parameters) {
+// them. Nothing is printed for an empty directory.
+pub fn simple_print(files: Vec<File>, parameters: &Parameters) {
     if files.len() == 1 {
-        print!("{}", color_print(&files[0]));
+        print!("{}", get_security_context_prefix(&files[0], 0, parameters));
+        print_bytes(&color_print(&files[0], parameters));
+        println!("{}", get_file_indicator(&files[0], parameters));
         return;
     }
-    // The terminal width is necessary to find how many columns are needed, see get_matrix_size().
-    let terminal_width = match get_terminal_width() {
-        Ok(terminal_width) => terminal_width,
-        Err(error_message) => {
-            println!("{}", error_message);
-            return;
-        }
-    };
-
-    // Remove all the files where the name starts with a dot, if the -a parameter was not included.
-    if parameters.include_dot_files == false {
-        files.retain(|file| !file.path_name.starts_with('.'));
-    }
     if files.is_empty() {
-        println!();
         return;
     }
-    let column_length = get_column_length(&files);
-    let (number_of_rows, number_of_columns) =
-        get_matrix_size(files.len(), terminal_width as usize, column_length);
-
-    let file_matrix = assemble_file_matrix(number_of_columns, number_of_rows, files);
-    transpose_print(file_matrix, column_length);
-}
-
-// Returns the size of the file matrix in terms of the number of rows and columns.
-pub fn get_matrix_size(
-    number_of_files: usize,
-    terminal_width: usize,
-    column_length: usize,
-) -> (usize, usize) {
-    // We first divide the width of the terminal the command is being used in, by the length
-    // of the columns (which is the length of the longest file name + 1 (for the spacing), see
-    // get_column_length()).
-    let initial_number_of_columns = (terminal_width) / column_length;
-    // Based on the initial estimate of columns, we can find the number of rows.
-    let mut number_of_rows = number_of_files / initial_number_of_columns;
-    // If the division had a rest, we need to add an additional row.
-    if number_of_files % initial_number_of_columns != 0 {
-        number_of_rows += 1;
-    }
-
-    // If the division had a rest, we need to add an additional column.
-    let leftover_files_column = if number_of_files % (number_of_rows) != 0 {
-        1
-    } else {
-        0
+    let longest_security_context = get_longest_security_context(&files);
+    let cells: Vec<GridCell> = files
+        .iter()
+        .map(|file| GridCell {
+            text: [
+                get_security_context_prefix(file, longest_security_context, parameters).as_bytes(),
+                &color_print(file, parameters),
+                get_file_indicator(file, parameters).as_bytes(),
+            ]
+            .concat(),
+            width: get_file_width(file, longest_security_context, parameters),
+        })
+        .collect();
+
+    // The terminal width is necessary to find how many columns are needed, see get_grid_layout().
+    // Without a terminal, the names are printed one per line like coreutils does.
+    let lines = match get_terminal_width() {
+        Some(terminal_width) => format_grid(&cells, terminal_width),
+        None => cells.into_iter().map(|cell| cell.text).collect(),
     };
-
-    // We can now find the exact right amount of needed columns.
-    let number_of_columns = number_of_files / number_of_rows + leftover_files_column;
-
-    (number_of_rows, number_of_columns)
-}
-
-// Creates a 2D vector of files, based on the 1D "files" vector. The rows and columns are inverted,
-// which is why they are first tranposed before being printed in transpose_print().
-fn assemble_file_matrix(
-    number_of_columns: usize,
-    number_of_rows: usize,
-    files: Vec<File>,
-) -> Vec<Vec<File>> {
-    let mut counter = 0;
-    let mut file_matrix: Vec<Vec<File>> = Vec::new();
-
-    for _ in 0..number_of_columns {
-        let mut row: Vec<File> = Vec::new();
-        while counter < files.len() {
-            row.push(files[counter].clone());
-            counter += 1;
-            if counter % number_of_rows == 0 {
-                break;
-            }
-        }
-        file_matrix.push(row);
-    }
-    return file_matrix;
-}
-
-// In order to correctly display the files, we transpose the existing 2d vector of files
-// called "file_matrix" because the rows and columns are inverted.
-fn transpose_print(file_matrix: Vec<Vec<File>>, column_length: usize) {
-    // Find the maximum number of rows and columns.
-    // Certain columns may have different sizes, so we can't just use file_matrix[0].len() as
-    // length to find the number of needed columns.
-    let number_of_rows = file_matrix.len();
-    let number_of_columns = file_matrix.iter().map(|row| row.len()).max().unwrap_or(0);
-
-    for column in 0..number_of_columns {
-        for row in 0..number_of_rows {
-            if column < file_matrix[row].len() {
-                print!("{}", color_print(&file_matrix[row][column]));
-                let counter = column_length - file_matrix[row][column].path_name.len();
-                for _ in 0..counter {
-                    print!(" ");
-                }
-            }
-        }
+    for line in lines {
+        print_bytes(&line);
         println!();
     }
 }
 
 // Handles function calls without any parameter or multiple arguments, is also used to handle each
 // argument independently.
-pub fn one_argument(target_path: &str, parameters: &Parameters) -> Result<Vec<File>, String> {
-    let path = Path::new(target_path);
-
-    match read_dir(&path) {
-        Ok(path) => Ok(create_files_vector(path, parameters)),
-        Err(_) => Err(format!("ls: {}: No such file or directory", target_path).to_string()),
+pub fn one_argument(target_path: &Path, parameters: &Parameters) -> Result<Vec<File>, String> {
+    match read_dir(target_path) {
+        Ok(path) => Ok(create_files_vector(target_path, path, parameters)),
+        Err(error) => Err(format!(
+            "cannot open directory {}: {}",
+            quote_name_in_message(target_path.as_os_str()),
+            describe_io_error(&error)
+        )),
     }
 }
@@ -1,3 +1,5 @@
+pub mod dircolors;
 pub mod folders;
 pub mod single_files;
+pub mod tree;
 pub mod unexisting_files;
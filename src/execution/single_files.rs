@@ -1,13 +1,16 @@
+#![allow(clippy::bool_comparison, clippy::needless_return, clippy::ptr_arg)]
+
 use crate::*;
+use std::ffi::OsString;
 
 // Returns a Vector with the arguments that are single files, leaving folders.
-fn find_single_files(args: &mut Vec<String>) -> Vec<String> {
+fn find_single_files(args: &mut Vec<OsString>, parameters: &Parameters) -> Vec<OsString> {
     let mut counter = 0;
-    let mut single_files: Vec<String> = Vec::new();
+    let mut single_files: Vec<OsString> = Vec::new();
 
     while counter != args.len() {
-        if is_file(&args[counter]) {
-            single_files.push(args[counter].to_string());
+        if is_command_line_file(&args[counter], parameters) {
+            single_files.push(args[counter].clone());
         }
         counter += 1;
     }
@@ -16,7 +19,7 @@ fn find_single_files(args: &mut Vec<String>) -> Vec<String> {
 }
 
 // Remove the single files from the args vector, as they have already been processed.
-fn remove_single_files(args: &mut Vec<String>, single_files: &mut Vec<String>) {
+fn remove_single_files(args: &mut Vec<OsString>, single_files: &mut Vec<OsString>) {
     let mut counter = 0;
 
     while counter != single_files.len() {
@@ -26,74 +29,58 @@ fn remove_single_files(args: &mut Vec<String>, single_files: &mut Vec<String>) {
     }
 }
 
-// Creates a 2D vector of files, based on the 1D "files" vector. The rows and columns are inverted,
-// which is why they are first tranposed before being printed in transpose_print().
-fn assemble_file_matrix_single_files(
-    number_of_columns: usize,
-    number_of_rows: usize,
-    files: &Vec<String>,
-) -> Vec<Vec<String>> {
-    let mut counter = 0;
-    let mut file_matrix: Vec<Vec<String>> = Vec::new();
-
-    for _ in 0..number_of_columns {
-        let mut row: Vec<String> = Vec::new();
-        while counter < files.len() {
-            row.push(files[counter].clone());
-            counter += 1;
-            if counter % number_of_rows == 0 {
-                break;
-            }
-        }
-        file_matrix.push(row);
-    }
-    return file_matrix;
-}
-
-// In order to correctly display the files, we transpose the existing 2d vector of files
-// called "file_matrix" because the rows and columns are inverted.
-fn transpose_print_single_files(file_matrix: Vec<Vec<String>>, column_length: usize) {
-    // Find the maximum number of rows and columns.
-    // Certain columns may have different sizes, so we can't just use file_matrix[0].len() as
-    // length to find the number of needed columns.
-    let number_of_rows = file_matrix.len();
-    let number_of_columns = file_matrix.iter().map(|row| row.len()).max().unwrap_or(0);
-
-    for column in 0..number_of_columns {
-        for row in 0..number_of_rows {
-            if column < file_matrix[row].len() {
-                print!("{}", file_matrix[row][column]);
-                let counter = column_length - file_matrix[row][column].len();
-                for _ in 0..counter {
-                    print!(" ");
-                }
-            }
-        }
-        println!();
-    }
-}
-
 // The function used when all that is needed is to output the files, without information about
 // them.
-fn simple_print_single_files(files: &Vec<String>) {
-    // The terminal width is necessary to find how many columns are needed, see get_matrix_size().
-    let terminal_width = match get_terminal_width() {
-        Ok(terminal_width) => terminal_width,
-        Err(error_message) => {
-            println!("{}", error_message);
-            return;
-        }
+fn simple_print_single_files(files: &Vec<OsString>, parameters: &Parameters) {
+    // The indicators and security contexts are part of the names, so that they are counted in the
+    // column widths.
+    let names: Vec<Vec<u8>> = if parameters.indicator_style == IndicatorStyle::None
+        && parameters.security_context == false
+    {
+        files
+            .iter()
+            .map(|name| quote_name(name, parameters))
+            .collect()
+    } else {
+        let file_vector = convert_string_vector_to_file_vector(files.clone(), parameters);
+        let longest_security_context = get_longest_security_context(&file_vector);
+
+        file_vector
+            .iter()
+            .zip(files)
+            .map(|(file, name)| {
+                [
+                    get_security_context_prefix(file, longest_security_context, parameters)
+                        .as_bytes(),
+                    &quote_name(name, parameters),
+                    get_file_indicator(file, parameters).as_bytes(),
+                ]
+                .concat()
+            })
+            .collect()
     };
 
-    let column_length = get_column_length_single_files(&files);
-    let (number_of_rows, number_of_columns) =
-        get_matrix_size(files.len(), terminal_width as usize, column_length);
-
-    let file_matrix = assemble_file_matrix_single_files(number_of_columns, number_of_rows, &files);
-    transpose_print_single_files(file_matrix, column_length);
+    let cells: Vec<GridCell> = names
+        .into_iter()
+        .map(|name| GridCell {
+            width: get_display_width(&name),
+            text: name,
+        })
+        .collect();
+
+    // The terminal width is necessary to find how many columns are needed, see get_grid_layout().
+    // Without a terminal, the names are printed one per line like coreutils does.
+    let lines = match get_terminal_width() {
+        Some(terminal_width) => format_grid(&cells, terminal_width),
+        None => cells.into_iter().map(|cell| cell.text).collect(),
+    };
+    for line in lines {
+        print_bytes(&line);
+        println!();
+    }
 }
 
-fn string_rank_redirect(strings: &mut Vec<String>, parameters: &Parameters) {
+fn string_rank_redirect(strings: &mut Vec<OsString>, parameters: &Parameters) {
     if parameters.reverse_order == true {
         if parameters.last_modified_order == true {
             reverse_rank_path_by_last_modified_date(strings);
@@ -111,8 +98,12 @@ fn string_rank_redirect(strings: &mut Vec<String>, parameters: &Parameters) {
 
 // Takes care of printing single files, meaning files that are explicitly mentioned in the command
 // (as opposed to files in a mentioned folder, for example).
-pub fn handle_single_files(args: &mut Vec<String>, parameters: &Parameters) -> bool {
-    let mut single_files = find_single_files(args);
+pub fn handle_single_files(
+    args: &mut Vec<OsString>,
+    git_cache: &mut GitCache,
+    parameters: &Parameters,
+) -> bool {
+    let mut single_files = find_single_files(args, parameters);
 
     remove_single_files(args, &mut single_files);
 
@@ -121,12 +112,13 @@ pub fn handle_single_files(args: &mut Vec<String>, parameters: &Parameters) -> b
     if !single_files.is_empty() {
         if parameters.long_format == true {
             long_format_print(
-                convert_string_vector_to_file_vector(single_files),
+                convert_string_vector_to_file_vector(single_files, parameters),
+                git_cache,
                 parameters,
                 true,
             );
         } else {
-            simple_print_single_files(&single_files);
+            simple_print_single_files(&single_files, parameters);
         }
         return false;
     }
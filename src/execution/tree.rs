@@ -0,0 +1,217 @@
+#![allow(clippy::bool_comparison)]
+
+use crate::*;
+use std::env;
+use std::ffi::OsString;
+use std::path::Path;
+
+// The characters used to draw the tree's branches.
+struct TreeCharacters {
+    branch: &'static str,
+    last_branch: &'static str,
+    vertical_line: &'static str,
+    empty: &'static str,
+}
+
+// A line of the tree: the file, and the branches drawn before its name.
+struct TreeLine {
+    file: File,
+    prefix: String,
+}
+
+// Checks whether the locale uses UTF-8, looking at the variables in the same order as setlocale().
+fn locale_uses_utf8() -> bool {
+    for variable in ["LC_ALL", "LC_CTYPE", "LANG"] {
+        if let Ok(value) = env::var(variable) {
+            if !value.is_empty() {
+                let value = value.to_lowercase();
+                return value.contains("utf-8") || value.contains("utf8");
+            }
+        }
+    }
+    false
+}
+
+// Box-drawing characters are used if the locale supports them, ASCII ones otherwise.
+fn get_tree_characters() -> TreeCharacters {
+    if locale_uses_utf8() == true {
+        TreeCharacters {
+            branch: "├── ",
+            last_branch: "└── ",
+            vertical_line: "│   ",
+            empty: "    ",
+        }
+    } else {
+        TreeCharacters {
+            branch: "|-- ",
+            last_branch: "`-- ",
+            vertical_line: "|   ",
+            empty: "    ",
+        }
+    }
+}
+
+// Reads the directory and its subdirectories, adding a line for each file with the branches
+// leading to it. The files are ordered like in the other outputs, and the same rules as for -R
+// apply to choose which directories are entered (visibility, -L, --max-depth and cycles).
+fn collect_tree_lines(
+    path: &Path,
+    prefix: &str,
+    lines: &mut Vec<TreeLine>,
+    characters: &TreeCharacters,
+    ancestors: &mut Vec<(u64, u64)>,
+    depth: usize,
+    parameters: &Parameters,
+) {
+    let mut files = match one_argument(path, parameters) {
+        Ok(files) => files,
+        Err(error_message) => {
+            // Only the argument itself is given on the command line.
+            let status = if depth == 0 {
+                SERIOUS_PROBLEM
+            } else {
+                MINOR_PROBLEM
+            };
+            report_error(&error_message, status);
+            return;
+        }
+    };
+    files.retain(|file| file.path_name != "." && file.path_name != "..");
+
+    let number_of_files = files.len();
+    for (index, file) in files.into_iter().enumerate() {
+        let is_last = index == number_of_files - 1;
+        let branch = if is_last {
+            characters.last_branch
+        } else {
+            characters.branch
+        };
+        let is_entered = file.is_dir == true
+            && parameters
+                .max_depth
+                .is_none_or(|max_depth| depth < max_depth);
+        let full_path = file.full_path.clone();
+
+        lines.push(TreeLine {
+            file,
+            prefix: format!("{}{}", prefix, branch),
+        });
+
+        if is_entered == false {
+            continue;
+        }
+        let identifier = get_directory_identifier(&full_path);
+        if let Some(identifier) = identifier {
+            if ancestors.contains(&identifier) {
+                report_error(
+                    &format!(
+                        "{}: not listing already-listed directory",
+                        quote_name_before_colon(full_path.as_os_str())
+                    ),
+                    SERIOUS_PROBLEM,
+                );
+                continue;
+            }
+            ancestors.push(identifier);
+        }
+
+        let child_prefix = if is_last {
+            format!("{}{}", prefix, characters.empty)
+        } else {
+            format!("{}{}", prefix, characters.vertical_line)
+        };
+        collect_tree_lines(
+            &full_path,
+            &child_prefix,
+            lines,
+            characters,
+            ancestors,
+            depth + 1,
+            parameters,
+        );
+        if identifier.is_some() {
+            ancestors.pop();
+        }
+    }
+}
+
+// Prints the collected lines, with the long format columns to the left of the tree if the -l
+// parameter was included.
+fn print_tree(lines: Vec<TreeLine>, git_cache: &mut GitCache, parameters: &Parameters) {
+    if parameters.long_format == true {
+        let files = lines.iter().map(|line| &line.file);
+        let longest_number = get_longest_number_of_links(files.clone());
+        let longest_security_context = get_longest_security_context(files.clone());
+        let longest_file_size = get_longest_file_size(files);
+
+        for line in lines {
+            print_long_format_columns(
+                &line.file,
+                longest_number,
+                longest_security_context,
+                longest_file_size,
+                git_cache,
+                parameters,
+            );
+            print!("{}", line.prefix);
+            print_file_name_long_format(&line.file, parameters);
+        }
+    } else {
+        let longest_security_context =
+            get_longest_security_context(lines.iter().map(|line| &line.file));
+
+        for line in lines {
+            print!(
+                "{}{}",
+                get_security_context_prefix(&line.file, longest_security_context, parameters),
+                line.prefix
+            );
+            print_bytes(&color_print(&line.file, parameters));
+            println!("{}", get_file_indicator(&line.file, parameters));
+        }
+    }
+}
+
+// Called when the --tree parameter is included: each argument is printed with its whole hierarchy
+// below it, instead of the flat "directory:" blocks of -R.
+pub fn handle_tree(args: &Vec<OsString>, git_cache: &mut GitCache, parameters: &Parameters) {
+    let characters = get_tree_characters();
+
+    for argument in args {
+        let path = Path::new(argument);
+        let metadata = match path.symlink_metadata() {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        let mut root = File::new(
+            argument.clone(),
+            path,
+            metadata,
+            follows_command_line_symbolic_links(parameters)
+                || parameters.dereference == DereferenceMode::CommandLineSymlinkToDir,
+        );
+        if parameters.security_context == true {
+            root.security_context = get_security_context(&root);
+        }
+        let is_entered = root.is_dir;
+
+        let mut lines = vec![TreeLine {
+            file: root,
+            prefix: String::new(),
+        }];
+        if is_entered == true {
+            let mut ancestors: Vec<(u64, u64)> =
+                get_directory_identifier(path).into_iter().collect();
+            collect_tree_lines(
+                path,
+                "",
+                &mut lines,
+                &characters,
+                &mut ancestors,
+                0,
+                parameters,
+            );
+        }
+        print_tree(lines, git_cache, parameters);
+    }
+}
@@ -1,13 +1,17 @@
+#![allow(clippy::ptr_arg)]
+
 use crate::*;
+use std::ffi::OsString;
+use std::path::Path;
 
 // The name says it all: catch all the files that do not exist.
-fn find_unexisting_files(args: &mut Vec<String>) -> Vec<String> {
+fn find_unexisting_files(args: &mut Vec<OsString>) -> Vec<OsString> {
     let mut counter = 0;
-    let mut unexisting_files: Vec<String> = Vec::new();
+    let mut unexisting_files: Vec<OsString> = Vec::new();
 
     while counter != args.len() {
         if !file_exists(&args[counter]) {
-            unexisting_files.push(args[counter].to_string());
+            unexisting_files.push(args[counter].clone());
         }
         counter += 1;
     }
@@ -16,7 +20,7 @@ fn find_unexisting_files(args: &mut Vec<String>) -> Vec<String> {
 }
 
 // Remove the non-existent files from args vector, to avoid duplicate processing.
-fn remove_unexisting_files(args: &mut Vec<String>, unexisting_files: &mut Vec<String>) {
+fn remove_unexisting_files(args: &mut Vec<OsString>, unexisting_files: &mut Vec<OsString>) {
     let mut counter = 0;
 
     while counter != unexisting_files.len() {
@@ -26,15 +30,24 @@ fn remove_unexisting_files(args: &mut Vec<String>, unexisting_files: &mut Vec<St
     }
 }
 
-// Prints the error message for the files that could not be found.
-fn simple_print_unexisting_files(unexisting_files: &Vec<String>) {
+// Reports the files that could not be accessed, which is a serious problem as they were explicitly
+// asked for. The reason is usually that they don't exist, but can also be a lack of permission on
+// one of their parent directories, a component that isn't a directory...
+fn simple_print_unexisting_files(unexisting_files: &Vec<OsString>) {
     for i in unexisting_files {
-        println!("ls: {}: No such file or directory", i);
+        let reason = match Path::new(i).symlink_metadata() {
+            Err(error) => describe_io_error(&error),
+            Ok(_) => String::from("No such file or directory"),
+        };
+        report_error(
+            &format!("cannot access {}: {}", quote_name_in_message(i), reason),
+            SERIOUS_PROBLEM,
+        );
     }
 }
 
 // Called at the beginning of the program's flow, to catch non-existent files directly.
-pub fn handle_unexisting_files(args: &mut Vec<String>) {
+pub fn handle_unexisting_files(args: &mut Vec<OsString>) {
     let mut unexisting_files = find_unexisting_files(args);
 
     remove_unexisting_files(args, &mut unexisting_files);
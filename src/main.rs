@@ -1,24 +1,68 @@
 use std::env;
+use std::io::{stdout, Write};
+use std::path::Path;
+use std::process;
+
+// The print!() and println!() of the standard library panic when the standard output can't be
+// written to. These replace them in the whole crate, reporting the error like coreutils instead.
+macro_rules! print {
+    ($($argument:tt)*) => {
+        if let Err(error) = std::io::Write::write_fmt(&mut std::io::stdout(), format_args!($($argument)*)) {
+            $crate::report_write_error(error);
+        }
+    };
+}
+
+macro_rules! println {
+    () => {
+        print!("\n")
+    };
+    ($($argument:tt)*) => {
+        print!("{}\n", format_args!($($argument)*))
+    };
+}
 
 mod utilities;
+use utilities::attributes::*;
+use utilities::capabilities::*;
+use utilities::colors::*;
+use utilities::git::*;
+use utilities::grid::*;
 use utilities::helpers::*;
+use utilities::quoting::*;
+use utilities::statx::*;
 use utilities::structs::*;
+use utilities::workers::*;
 
 mod execution;
+use execution::dircolors::*;
 use execution::folders::*;
 use execution::single_files::*;
+use execution::tree::*;
 use execution::unexisting_files::*;
 
 #[allow(warnings)]
 
 fn main() {
-    if env::args().len() == 1 {
+    // Rust ignores SIGPIPE, which makes print!() panic once the reader of a pipe is gone (e.g.
+    // "ls | head"). Like other command line tools, ls should just stop instead.
+    // SAFETY: no other thread is running yet, and the default action takes no handler.
+    unsafe {
+        libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+    }
+
+    if env::args_os().len() == 1 {
         let parameters = Parameters::new();
-        match one_argument("./", &parameters) {
+        match one_argument(Path::new("./"), &parameters) {
             Ok(files) => simple_print(files, &parameters),
-            Err(error_message) => println!("{}", error_message),
+            Err(error_message) => report_error(&error_message, SERIOUS_PROBLEM),
         }
     } else {
-        handle_command(env::args().collect());
+        handle_command(env::args_os().collect());
+    }
+    // The end of the listing is still buffered if it doesn't end with a newline.
+    if let Err(error) = stdout().flush() {
+        report_write_error(error);
     }
+    process::exit(get_exit_status());
 }
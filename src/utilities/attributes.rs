@@ -0,0 +1,152 @@
+#![allow(clippy::bool_comparison)]
+
+use crate::*;
+use std::io::{Error, ErrorKind};
+
+// The ext2-style inode flags shown by --attrs, in the order and with the letters "lsattr" uses.
+// Unset flags are shown as "-".
+const ATTRIBUTE_FLAGS: [(u64, char); 22] = [
+    (0x00000001, 's'), // secure deletion
+    (0x00000002, 'u'), // undeletable
+    (0x00000008, 'S'), // synchronous updates
+    (0x00010000, 'D'), // synchronous directory updates
+    (0x00000010, 'i'), // immutable
+    (0x00000020, 'a'), // append only
+    (0x00000040, 'd'), // no dump
+    (0x00000080, 'A'), // no atime updates
+    (0x00000004, 'c'), // compressed
+    (0x00000800, 'E'), // encrypted
+    (0x00004000, 'j'), // data journaling
+    (0x00001000, 'I'), // indexed directory
+    (0x00008000, 't'), // no tail-merging
+    (0x00020000, 'T'), // top of directory hierarchy
+    (0x00080000, 'e'), // extents
+    (0x00800000, 'C'), // no copy on write
+    (0x02000000, 'x'), // direct access
+    (0x40000000, 'F'), // case-insensitive directory
+    (0x10000000, 'N'), // inline data
+    (0x20000000, 'P'), // project hierarchy
+    (0x00100000, 'V'), // verity
+    (0x00000400, 'm'), // don't compress
+];
+
+// The STATX_ATTR_* flags statx() reports, and the inode flag each one corresponds to. Used when
+// the file can't be opened to read its inode flags.
+const STATX_ATTRIBUTES: [(u64, u64); 7] = [
+    (0x00000004, 0x00000004), // compressed
+    (0x00000010, 0x00000010), // immutable
+    (0x00000020, 0x00000020), // append only
+    (0x00000040, 0x00000040), // no dump
+    (0x00000800, 0x00000800), // encrypted
+    (0x00100000, 0x00100000), // verity
+    (0x00200000, 0x02000000), // direct access
+];
+
+// Reads the inode flags with the FS_IOC_GETFLAGS ioctl, like "lsattr" does. Only regular files and
+// directories are opened, as opening devices or FIFOs may have side effects, and the flags of
+// symbolic links can't be read. The request number is built with the generic ioctl encoding, so
+// this is limited to the architectures using it (not e.g. powerpc, mips or sparc).
+#[cfg(all(
+    target_os = "linux",
+    any(
+        target_arch = "x86",
+        target_arch = "x86_64",
+        target_arch = "arm",
+        target_arch = "aarch64",
+        target_arch = "riscv64",
+        target_arch = "s390x",
+        target_arch = "loongarch64"
+    )
+))]
+fn get_inode_flags(file: &File) -> Result<u64, Error> {
+    use std::fs::OpenOptions;
+    use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+    use std::os::unix::io::AsRawFd;
+
+    // FS_IOC_GETFLAGS, i.e. _IOR('f', 1, long), with the generic ioctl encoding.
+    const FS_IOC_GETFLAGS: libc::c_ulong = (2 << 30)
+        | ((std::mem::size_of::<libc::c_long>() as libc::c_ulong) << 16)
+        | ((b'f' as libc::c_ulong) << 8)
+        | 1;
+
+    let file_type = file.full_path.symlink_metadata()?.file_type();
+    let is_openable = file.is_symbolic_link == false
+        && !file_type.is_fifo()
+        && !file_type.is_socket()
+        && !file_type.is_block_device()
+        && !file_type.is_char_device();
+    if is_openable == false {
+        return Err(Error::from(ErrorKind::Unsupported));
+    }
+
+    let opened_file = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(&file.full_path)?;
+    let mut flags: libc::c_long = 0;
+    // The request is an int with musl and an unsigned long with glibc, hence the cast.
+    // SAFETY: the descriptor is open for the duration of the call, and the ioctl writes at most a
+    // long to the given pointer.
+    let result = unsafe { libc::ioctl(opened_file.as_raw_fd(), FS_IOC_GETFLAGS as _, &mut flags) };
+    if result != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(flags as u64 & 0xffffffff)
+}
+
+// Elsewhere, the inode flags can't be read this way, and --attrs only shows the ones statx()
+// reports.
+#[cfg(not(all(
+    target_os = "linux",
+    any(
+        target_arch = "x86",
+        target_arch = "x86_64",
+        target_arch = "arm",
+        target_arch = "aarch64",
+        target_arch = "riscv64",
+        target_arch = "s390x",
+        target_arch = "loongarch64"
+    )
+)))]
+fn get_inode_flags(_file: &File) -> Result<u64, Error> {
+    Err(Error::from(ErrorKind::Unsupported))
+}
+
+// Converts the STATX_ATTR_* flags statx() reports into inode flags. Returns None if the file
+// system supports none of them (flags like STATX_ATTR_MOUNT_ROOT are reported everywhere).
+fn get_statx_flags(file: &File) -> Option<u64> {
+    let metadata = get_extended_metadata(&file.full_path, !file.is_symbolic_link)?;
+    let is_supported = STATX_ATTRIBUTES
+        .iter()
+        .any(|(statx_attribute, _)| metadata.attributes_mask & statx_attribute != 0);
+    if is_supported == false {
+        return None;
+    }
+
+    let mut flags = 0;
+    for (statx_attribute, flag) in STATX_ATTRIBUTES {
+        if metadata.attributes & statx_attribute != 0 {
+            flags |= flag;
+        }
+    }
+    Some(flags)
+}
+
+// Returns the file's inode flags in the format of "lsattr", e.g. "----i---------e-------", or "?"
+// if the file system doesn't support them.
+pub fn get_attribute_flags(file: &File) -> String {
+    let flags = match get_inode_flags(file) {
+        Ok(flags) => Some(flags),
+        Err(error) if error.kind() == ErrorKind::PermissionDenied => get_statx_flags(file),
+        Err(_) => None,
+    };
+    let flags = match flags {
+        Some(flags) => flags,
+        None => return "?".to_string(),
+    };
+
+    ATTRIBUTE_FLAGS
+        .iter()
+        .map(|(flag, letter)| if flags & flag != 0 { *letter } else { '-' })
+        .collect()
+}
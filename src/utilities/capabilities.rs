@@ -0,0 +1,257 @@
+#![allow(clippy::bool_comparison)]
+
+use crate::*;
+
+// The capability names, indexed by their number (see "man 7 capabilities").
+const CAPABILITY_NAMES: [&str; 41] = [
+    "cap_chown",
+    "cap_dac_override",
+    "cap_dac_read_search",
+    "cap_fowner",
+    "cap_fsetid",
+    "cap_kill",
+    "cap_setgid",
+    "cap_setuid",
+    "cap_setpcap",
+    "cap_linux_immutable",
+    "cap_net_bind_service",
+    "cap_net_broadcast",
+    "cap_net_admin",
+    "cap_net_raw",
+    "cap_ipc_lock",
+    "cap_ipc_owner",
+    "cap_sys_module",
+    "cap_sys_rawio",
+    "cap_sys_chroot",
+    "cap_sys_ptrace",
+    "cap_sys_pacct",
+    "cap_sys_admin",
+    "cap_sys_boot",
+    "cap_sys_nice",
+    "cap_sys_resource",
+    "cap_sys_time",
+    "cap_sys_tty_config",
+    "cap_mknod",
+    "cap_lease",
+    "cap_audit_write",
+    "cap_audit_control",
+    "cap_setfcap",
+    "cap_mac_override",
+    "cap_mac_admin",
+    "cap_syslog",
+    "cap_wake_alarm",
+    "cap_block_suspend",
+    "cap_audit_read",
+    "cap_perfmon",
+    "cap_bpf",
+    "cap_checkpoint_restore",
+];
+
+// The layout of the "security.capability" extended attribute (struct vfs_ns_cap_data in the
+// kernel): a little-endian magic number holding the version and the effective flag, then a
+// permitted and an inheritable 32-bit set per 32 capabilities, then the root user id of the user
+// namespace for version 3.
+const VERSION_MASK: u32 = 0xff000000;
+const VERSION_1: u32 = 0x01000000;
+const VERSION_2: u32 = 0x02000000;
+const VERSION_3: u32 = 0x03000000;
+const EFFECTIVE_FLAG: u32 = 0x000001;
+
+// The decoded content of the "security.capability" extended attribute.
+struct FileCapabilities {
+    permitted: u64,
+    inheritable: u64,
+    effective: bool,
+    root_id: Option<u32>,
+}
+
+// Reads the little-endian 32-bit number at the given index of the attribute.
+fn read_u32(data: &[u8], index: usize) -> Option<u32> {
+    let bytes = data.get(index * 4..index * 4 + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+// Decodes the attribute, returning None if its version or size is unknown.
+fn parse_capabilities(data: &[u8]) -> Option<FileCapabilities> {
+    let magic = read_u32(data, 0)?;
+    let (number_of_sets, expected_size) = match magic & VERSION_MASK {
+        VERSION_1 => (1, 12),
+        VERSION_2 => (2, 20),
+        VERSION_3 => (2, 24),
+        _ => return None,
+    };
+    if data.len() != expected_size {
+        return None;
+    }
+
+    let mut permitted: u64 = 0;
+    let mut inheritable: u64 = 0;
+    for set in 0..number_of_sets {
+        permitted |= (read_u32(data, 1 + set * 2)? as u64) << (32 * set);
+        inheritable |= (read_u32(data, 2 + set * 2)? as u64) << (32 * set);
+    }
+
+    Some(FileCapabilities {
+        permitted,
+        inheritable,
+        effective: magic & EFFECTIVE_FLAG != 0,
+        root_id: if magic & VERSION_MASK == VERSION_3 {
+            read_u32(data, 5)
+        } else {
+            None
+        },
+    })
+}
+
+// Returns the name of the capability with the given number.
+fn get_capability_name(number: usize) -> String {
+    match CAPABILITY_NAMES.get(number) {
+        Some(name) => name.to_string(),
+        None => format!("cap_{}", number),
+    }
+}
+
+// Formats the capabilities like "getcap" does, the ones sharing the same flags being grouped
+// together, e.g. "cap_net_admin,cap_net_raw=ep cap_sys_time=i".
+fn format_capabilities(capabilities: &FileCapabilities) -> String {
+    let mut groups: Vec<(String, Vec<String>)> = Vec::new();
+
+    for number in 0..64 {
+        let is_permitted = capabilities.permitted & (1 << number) != 0;
+        let is_inheritable = capabilities.inheritable & (1 << number) != 0;
+        if is_permitted == false && is_inheritable == false {
+            continue;
+        }
+
+        let mut flags = String::new();
+        if capabilities.effective == true {
+            flags.push('e');
+        }
+        if is_inheritable == true {
+            flags.push('i');
+        }
+        if is_permitted == true {
+            flags.push('p');
+        }
+        match groups
+            .iter_mut()
+            .find(|(group_flags, _)| *group_flags == flags)
+        {
+            Some((_, names)) => names.push(get_capability_name(number)),
+            None => groups.push((flags, vec![get_capability_name(number)])),
+        }
+    }
+
+    let mut formatted = groups
+        .iter()
+        .map(|(flags, names)| format!("{}={}", names.join(","), flags))
+        .collect::<Vec<String>>()
+        .join(" ");
+    if let Some(root_id) = capabilities.root_id.filter(|root_id| *root_id != 0) {
+        formatted.push_str(&format!(" [rootid={}]", root_id));
+    }
+    formatted
+}
+
+// Returns the file's capabilities in a human-readable form, or None if it has none.
+pub fn get_capabilities(file: &File) -> Option<String> {
+    let data = get_extended_attribute(file, "security.capability")?;
+    let capabilities = parse_capabilities(&data)?;
+
+    if capabilities.permitted == 0 && capabilities.inheritable == 0 {
+        return None;
+    }
+    Some(format_capabilities(&capabilities))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Decodes the hexadecimal form of an attribute, as printed by "getfattr -e hex".
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).unwrap())
+            .collect()
+    }
+
+    // Decodes and formats an attribute, panicking if it isn't valid.
+    fn format_attribute(hex: &str) -> String {
+        format_capabilities(&parse_capabilities(&from_hex(hex)).unwrap())
+    }
+
+    // The attributes below were written by "setcap", and the expected outputs are those of
+    // "getcap".
+    #[test]
+    fn version_2_capabilities_are_grouped_by_flags() {
+        assert_eq!(
+            format_attribute("0100000200300000000000000000000000000000"),
+            "cap_net_admin,cap_net_raw=ep"
+        );
+        assert_eq!(
+            format_attribute("0100000201000000010000000000000000000000"),
+            "cap_chown=eip"
+        );
+        // cap_bpf is in the second set of 32 capabilities. getcap writes these sets as
+        // "cap_sys_time=i cap_bpf+p", which means the same.
+        assert_eq!(
+            format_attribute("0000000200000000000000028000000000000000"),
+            "cap_sys_time=i cap_bpf=p"
+        );
+    }
+
+    #[test]
+    fn version_3_capabilities_show_the_root_id() {
+        let capabilities = parse_capabilities(&from_hex(
+            "0100000320000000000000000000000000000000e8030000",
+        ))
+        .unwrap();
+        assert_eq!(capabilities.root_id, Some(1000));
+        assert_eq!(
+            format_capabilities(&capabilities),
+            "cap_kill=ep [rootid=1000]"
+        );
+
+        // The root id of the initial user namespace isn't shown.
+        assert_eq!(
+            format_attribute("010000032000000000000000000000000000000000000000"),
+            "cap_kill=ep"
+        );
+    }
+
+    #[test]
+    fn version_1_capabilities_have_a_single_set() {
+        let capabilities = parse_capabilities(&from_hex("010000010004000000000000")).unwrap();
+        assert_eq!(capabilities.root_id, None);
+        assert_eq!(
+            format_capabilities(&capabilities),
+            "cap_net_bind_service=ep"
+        );
+    }
+
+    #[test]
+    fn unknown_versions_and_sizes_are_rejected() {
+        assert!(
+            parse_capabilities(&from_hex("0100000420000000000000000000000000000000")).is_none()
+        );
+        // A version 2 attribute with the size of a version 3 one.
+        assert!(parse_capabilities(&from_hex(
+            "010000022000000000000000000000000000000000000000"
+        ))
+        .is_none());
+        assert!(parse_capabilities(&from_hex("01000002200000")).is_none());
+        assert!(parse_capabilities(&[]).is_none());
+    }
+
+    #[test]
+    fn unknown_capabilities_are_named_by_their_number() {
+        let capabilities = FileCapabilities {
+            permitted: 1 << 41,
+            inheritable: 0,
+            effective: false,
+            root_id: None,
+        };
+        assert_eq!(format_capabilities(&capabilities), "cap_41=p");
+    }
+}
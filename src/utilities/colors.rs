@@ -0,0 +1,383 @@
+#![allow(clippy::bool_comparison)]
+
+use crate::*;
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsStr;
+use std::io::{stdout, IsTerminal};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::Path;
+
+// The colors used when the LS_COLORS environment variable is not set. Other file types use the
+// terminal's default color.
+pub const DEFAULT_COLORS: &str = "di=1;36:ln=35:or=40;31;1:ex=31";
+
+// When colors should be used, see the --color parameter.
+#[derive(PartialEq)]
+pub enum ColorMode {
+    Always,
+    Auto,
+    Never,
+}
+
+// The parsed content of LS_COLORS: one escape sequence per file type key ("di", "ln", "ex"...),
+// plus the ones matched against file names ("*.tar", "*.png"...). The sequences are made of
+// bytes, as their escapes can stand for any byte (e.g. "\xff").
+pub struct ColorDatabase {
+    types: HashMap<String, Vec<u8>>,
+    extensions: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl ColorDatabase {
+    // Parses a string in the LS_COLORS format, e.g. "di=01;34:ln=01;36:*.tar=01;31".
+    pub fn parse(ls_colors: &[u8]) -> ColorDatabase {
+        let mut database = ColorDatabase {
+            types: HashMap::new(),
+            extensions: Vec::new(),
+        };
+
+        for entry in ls_colors.split(|byte| *byte == b':') {
+            let (key, value) = match entry.iter().position(|byte| *byte == b'=') {
+                Some(position) => (
+                    &entry[..position],
+                    unescape_color_sequence(&entry[position + 1..]),
+                ),
+                None => continue,
+            };
+
+            if key.starts_with(b"*") {
+                database.extensions.push((key.to_vec(), value));
+            } else if !key.is_empty() {
+                database
+                    .types
+                    .insert(String::from_utf8_lossy(key).into_owned(), value);
+            }
+        }
+        database
+    }
+
+    // Returns the escape sequence (without the "\x1b[" and "m" around it) for a type key.
+    pub fn get(&self, key: &str) -> Option<&Vec<u8>> {
+        self.types.get(key).filter(|sequence| !sequence.is_empty())
+    }
+
+    // Returns the sequence of the last extension pattern matching the name, as later entries
+    // override earlier ones.
+    fn get_extension(&self, name: &[u8]) -> Option<&Vec<u8>> {
+        self.extensions
+            .iter()
+            .rev()
+            .find(|(pattern, _)| glob_match(pattern, name))
+            .map(|(_, sequence)| sequence)
+            .filter(|sequence| !sequence.is_empty())
+    }
+
+    // Checks whether "ln=target" was given, coloring the links like the files they point to.
+    fn colors_links_like_targets(&self) -> bool {
+        self.types.get("ln").map(|value| value.as_slice()) == Some(b"target")
+    }
+
+    // Whether coloring files requires more than their type, i.e. their permissions, their number
+    // of links or whether the symbolic links are broken.
+    pub fn needs_metadata(&self) -> bool {
+        ["or", "su", "sg", "ca", "tw", "ow", "st", "ex", "mh"]
+            .iter()
+            .any(|key| self.get(key).is_some())
+            || self.colors_links_like_targets()
+    }
+
+    // Wraps the text in the escape sequences of the given key, or leaves it untouched if the
+    // key has no color.
+    pub fn paint(&self, text: &[u8], sequence: Option<&Vec<u8>>) -> Vec<u8> {
+        let sequence = match sequence {
+            Some(sequence) => sequence,
+            None => return text.to_vec(),
+        };
+
+        let left = self.types.get("lc").map_or(&b"\x1b["[..], Vec::as_slice);
+        let right = self.types.get("rc").map_or(&b"m"[..], Vec::as_slice);
+        let end = match self.types.get("ec") {
+            Some(end) => end.clone(),
+            None => [
+                left,
+                self.types.get("rs").map_or(&b"0"[..], Vec::as_slice),
+                right,
+            ]
+            .concat(),
+        };
+        [left, sequence, right, text, &end].concat()
+    }
+
+    // Finds the sequence to use for a file, based on its type, permissions and name.
+    pub fn file_sequence(&self, file: &File) -> Option<&Vec<u8>> {
+        if file.is_symbolic_link == true {
+            if file.is_broken_symbolic_link == true {
+                return self.get("or").or_else(|| self.get("ln"));
+            }
+            // "ln=target" colors the link like the file it points to.
+            if self.colors_links_like_targets() == true {
+                return match file.full_path.metadata() {
+                    Ok(metadata) => self.mode_sequence(
+                        file.path_name.as_bytes(),
+                        metadata.mode(),
+                        metadata.nlink(),
+                        false,
+                    ),
+                    Err(_) => self.get("or"),
+                };
+            }
+            return self.get("ln");
+        }
+        // Reading the capabilities costs a system call, which is only worth it if they are
+        // colored.
+        let mode = file.file_mode.mode();
+        let has_capabilities = mode & 0o170000 == 0o100000
+            && self.get("ca").is_some()
+            && get_capabilities(file).is_some();
+        self.mode_sequence(
+            file.path_name.as_bytes(),
+            mode,
+            file.number_of_links,
+            has_capabilities,
+        )
+    }
+
+    // Finds the sequence to use for the target of a symbolic link, which is printed after the link
+    // in long format. Missing targets use the "mi" color.
+    pub fn symbolic_link_target_sequence(&self, file: &File, target: &OsStr) -> Option<&Vec<u8>> {
+        match file.full_path.metadata() {
+            Ok(metadata) => {
+                let name = Path::new(target).file_name().unwrap_or(target);
+                self.mode_sequence(name.as_bytes(), metadata.mode(), metadata.nlink(), false)
+            }
+            Err(_) => self.get("mi").or_else(|| self.get("or")),
+        }
+    }
+
+    // Finds the sequence for a file that is not a symbolic link, from its mode (which contains both
+    // its type and its permissions), its name, its number of hard links and whether it has file
+    // capabilities.
+    fn mode_sequence(
+        &self,
+        name: &[u8],
+        mode: u32,
+        number_of_links: u64,
+        has_capabilities: bool,
+    ) -> Option<&Vec<u8>> {
+        match mode & 0o170000 {
+            0o040000 => {
+                if mode & 0o1002 == 0o1002 {
+                    self.get("tw").or_else(|| self.get("di"))
+                } else if mode & 0o0002 != 0 {
+                    self.get("ow").or_else(|| self.get("di"))
+                } else if mode & 0o1000 != 0 {
+                    self.get("st").or_else(|| self.get("di"))
+                } else {
+                    self.get("di")
+                }
+            }
+            0o010000 => self.get("pi"),
+            0o140000 => self.get("so"),
+            0o060000 => self.get("bd"),
+            0o020000 => self.get("cd"),
+            _ => {
+                if mode & 0o4000 != 0 && self.get("su").is_some() {
+                    self.get("su")
+                } else if mode & 0o2000 != 0 && self.get("sg").is_some() {
+                    self.get("sg")
+                } else if has_capabilities == true {
+                    self.get("ca")
+                } else if mode & 0o111 != 0 && self.get("ex").is_some() {
+                    self.get("ex")
+                } else if let Some(sequence) = self.get_extension(name) {
+                    Some(sequence)
+                } else if number_of_links > 1 && self.get("mh").is_some() {
+                    self.get("mh")
+                } else {
+                    self.get("fi").or_else(|| self.get("no"))
+                }
+            }
+        }
+    }
+}
+
+// Builds the color database to use, or None if the output should not be colored.
+// NO_COLOR (see https://no-color.org) disables colors unless --color=always is explicitly given.
+pub fn get_color_database(color_mode: &ColorMode) -> Option<ColorDatabase> {
+    let enabled = match color_mode {
+        ColorMode::Always => true,
+        ColorMode::Never => false,
+        ColorMode::Auto => {
+            let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+            stdout().is_terminal() && !no_color
+        }
+    };
+
+    if enabled == false {
+        return None;
+    }
+    match env::var_os("LS_COLORS") {
+        Some(ls_colors) if !ls_colors.is_empty() => {
+            Some(ColorDatabase::parse(ls_colors.as_bytes()))
+        }
+        _ => Some(ColorDatabase::parse(DEFAULT_COLORS.as_bytes())),
+    }
+}
+
+// Turns the escapes allowed in LS_COLORS values ("\e", "^[", "\033", "\x1b"...) into the bytes
+// they stand for. Like coreutils, "\x" takes at most 2 hexadecimal digits and an octal escape at
+// most 3 digits.
+fn unescape_color_sequence(value: &[u8]) -> Vec<u8> {
+    let mut result: Vec<u8> = Vec::new();
+    let mut bytes = value.iter().copied().peekable();
+
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'\\' => match bytes.next() {
+                Some(b'e') | Some(b'E') => result.push(0x1b),
+                Some(b'a') => result.push(0x07),
+                Some(b'b') => result.push(0x08),
+                Some(b'f') => result.push(0x0c),
+                Some(b'n') => result.push(b'\n'),
+                Some(b'r') => result.push(b'\r'),
+                Some(b't') => result.push(b'\t'),
+                Some(b'v') => result.push(0x0b),
+                Some(b'?') => result.push(0x7f),
+                Some(b'_') => result.push(b' '),
+                Some(b'x') | Some(b'X') => {
+                    let mut value: u8 = 0;
+                    for _ in 0..2 {
+                        match bytes.peek().and_then(|digit| (*digit as char).to_digit(16)) {
+                            Some(digit) => value = value * 16 + digit as u8,
+                            None => break,
+                        }
+                        bytes.next();
+                    }
+                    result.push(value);
+                }
+                Some(digit @ b'0'..=b'7') => {
+                    // The value of 3 digits can exceed a byte, and is truncated like in coreutils.
+                    let mut value = (digit - b'0') as u32;
+                    for _ in 0..2 {
+                        match bytes.peek() {
+                            Some(digit @ b'0'..=b'7') => value = value * 8 + (digit - b'0') as u32,
+                            _ => break,
+                        }
+                        bytes.next();
+                    }
+                    result.push(value as u8);
+                }
+                Some(other) => result.push(other),
+                None => {}
+            },
+            b'^' => match bytes.next() {
+                Some(b'?') => result.push(0x7f),
+                Some(control) => result.push(control.to_ascii_uppercase() & 0x1f),
+                None => result.push(b'^'),
+            },
+            _ => result.push(byte),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_are_turned_into_bytes() {
+        let expected: [(&[u8], &[u8]); 9] = [
+            (b"01;34", b"01;34"),
+            (b"\\e[1m\\E", b"\x1b[1m\x1b"),
+            (b"^[^a^?", b"\x1b\x01\x7f"),
+            (
+                b"\\a\\b\\f\\n\\r\\t\\v\\?\\_",
+                b"\x07\x08\x0c\n\r\t\x0b\x7f ",
+            ),
+            (b"\\033\\0\\7", b"\x1b\x00\x07"),
+            (b"\\x1b\\xff\\XA", b"\x1b\xff\x0a"),
+            (b"\\:\\\\", b":\\"),
+            (b"trailing^", b"trailing^"),
+            (b"trailing\\", b"trailing"),
+        ];
+        for (value, sequence) in expected {
+            assert_eq!(unescape_color_sequence(value), sequence);
+        }
+    }
+
+    #[test]
+    fn escapes_only_take_the_digits_coreutils_reads() {
+        // Longer runs of digits are printed as they are after the escaped byte.
+        assert_eq!(unescape_color_sequence(b"\\x1b1b1b"), b"\x1b1b1b");
+        assert_eq!(unescape_color_sequence(b"\\0777777"), b"\x3f7777");
+        assert_eq!(unescape_color_sequence(b"\\777"), b"\xff");
+        assert_eq!(unescape_color_sequence(b"\\xg"), b"\x00g");
+    }
+
+    #[test]
+    fn special_permissions_take_precedence_over_extensions_and_links() {
+        let database = ColorDatabase::parse(b"su=1:sg=2:ca=3:ex=4:*.x=5:mh=6:fi=7");
+        let sequence = |name: &[u8], mode: u32, number_of_links: u64, has_capabilities: bool| {
+            database
+                .mode_sequence(name, mode, number_of_links, has_capabilities)
+                .cloned()
+        };
+
+        assert_eq!(sequence(b"a.x", 0o106755, 2, true), Some(b"1".to_vec()));
+        assert_eq!(sequence(b"a.x", 0o102755, 2, true), Some(b"2".to_vec()));
+        assert_eq!(sequence(b"a.x", 0o100755, 2, true), Some(b"3".to_vec()));
+        assert_eq!(sequence(b"a.x", 0o100755, 2, false), Some(b"4".to_vec()));
+        assert_eq!(sequence(b"a.x", 0o100644, 2, false), Some(b"5".to_vec()));
+        assert_eq!(sequence(b"a", 0o100644, 2, false), Some(b"6".to_vec()));
+        assert_eq!(sequence(b"a", 0o100644, 1, false), Some(b"7".to_vec()));
+
+        // Keys without a color don't stop the search.
+        let database = ColorDatabase::parse(b"su=:ex=4");
+        assert_eq!(
+            database.mode_sequence(b"a", 0o104755, 1, false),
+            Some(&b"4".to_vec())
+        );
+    }
+
+    #[test]
+    fn directories_are_colored_by_their_permissions() {
+        let database = ColorDatabase::parse(b"di=1:tw=2:ow=3:st=4");
+        let expected: [(u32, &[u8]); 4] = [
+            (0o041777, b"2"),
+            (0o040777, b"3"),
+            (0o041755, b"4"),
+            (0o040755, b"1"),
+        ];
+        for (mode, sequence) in expected {
+            assert_eq!(
+                database.mode_sequence(b"d", mode, 2, false),
+                Some(&sequence.to_vec())
+            );
+        }
+    }
+
+    #[test]
+    fn the_last_matching_extension_wins() {
+        let database = ColorDatabase::parse(b"*.gz=1:*.tar.gz=2:*z=3");
+        assert_eq!(database.get_extension(b"a.tar.gz"), Some(&b"3".to_vec()));
+        let database = ColorDatabase::parse(b"*z=3:*.gz=1:*.tar.gz=2");
+        assert_eq!(database.get_extension(b"a.tar.gz"), Some(&b"2".to_vec()));
+        assert_eq!(database.get_extension(b"b.gz"), Some(&b"1".to_vec()));
+    }
+
+    #[test]
+    fn painted_text_uses_the_left_right_and_end_codes() {
+        let database = ColorDatabase::parse(b"di=01;34");
+        assert_eq!(
+            database.paint(b"d", database.get("di")),
+            b"\x1b[01;34md\x1b[0m"
+        );
+        let database = ColorDatabase::parse(b"di=34:lc=<:rc=>:rs=9");
+        assert_eq!(database.paint(b"d", database.get("di")), b"<34>d<9>");
+        let database = ColorDatabase::parse(b"di=34:ec=\\e[m");
+        assert_eq!(database.paint(b"d", database.get("di")), b"\x1b[34md\x1b[m");
+        assert_eq!(database.paint(b"f", database.get("fi")), b"f");
+    }
+}
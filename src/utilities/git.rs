@@ -0,0 +1,1236 @@
+#![allow(clippy::bool_comparison)]
+
+use crate::*;
+use flate2::read::ZlibDecoder;
+use std::cell::OnceCell;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::fs::{read, read_dir, read_to_string};
+use std::io::Read;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+// A single entry of the git index (.git/index), holding the stat data git recorded when the file
+// was last staged, so it can be compared with the worktree.
+struct IndexEntry {
+    mtime_seconds: u32,
+    mtime_nanoseconds: u32,
+    mode: u32,
+    size: u32,
+    hash: [u8; 20],
+    stage: u16,
+}
+
+// A pattern coming from a .gitignore (or .git/info/exclude) file.
+struct IgnorePattern {
+    pattern: Vec<u8>,
+    negated: bool,
+    directory_only: bool,
+    anchored: bool,
+}
+
+// A pack file and its index. The index is read with the list of packs, the pack itself only once
+// an object is found in it.
+struct Pack {
+    index: Vec<u8>,
+    path: PathBuf,
+    data: OnceCell<Vec<u8>>,
+}
+
+// The hash and mode of each entry of a tree object, by name.
+type TreeEntries = HashMap<OsString, ([u8; 20], u32)>;
+
+// Everything needed to compute the status of the files of one working tree. The HEAD tree is read
+// lazily, one directory at a time, so that only the directories being listed are looked at.
+// Linked worktrees have their own HEAD and index in "git_directory", but share the objects and
+// the references of the main repository, found in "common_directory". The index is sorted, so
+// that the entries inside a directory follow each other, and the status of each entry and
+// whether each directory has untracked files are only computed once.
+pub struct GitRepository {
+    workdir: PathBuf,
+    git_directory: PathBuf,
+    common_directory: PathBuf,
+    index: BTreeMap<PathBuf, IndexEntry>,
+    conflicts: Vec<PathBuf>,
+    head_tree: Option<[u8; 20]>,
+    head_trees: HashMap<PathBuf, TreeEntries>,
+    packs: OnceCell<Vec<Pack>>,
+    ignore_patterns: HashMap<PathBuf, Vec<IgnorePattern>>,
+    tracked_statuses: HashMap<PathBuf, (char, char)>,
+    untracked_directories: HashMap<PathBuf, bool>,
+}
+
+// Keeps the repositories that were already loaded, so the index is only read once per command.
+// "directories" remembers which repository, if any, each looked up directory belongs to, as a
+// submodule's directory belongs to it rather than to the superproject around it.
+pub struct GitCache {
+    repositories: Vec<GitRepository>,
+    directories: HashMap<PathBuf, Option<usize>>,
+}
+
+impl GitCache {
+    pub fn new() -> GitCache {
+        GitCache {
+            repositories: Vec::new(),
+            directories: HashMap::new(),
+        }
+    }
+
+    // Returns the position of the repository the directory belongs to, loading it if needed.
+    fn find_repository(&mut self, directory: &Path) -> Option<usize> {
+        if let Some(position) = self.directories.get(directory) {
+            return *position;
+        }
+
+        let position = find_git_directory(directory).map(|(workdir, git_directory)| {
+            match self
+                .repositories
+                .iter()
+                .position(|repository| repository.workdir == workdir)
+            {
+                Some(position) => position,
+                None => {
+                    self.repositories
+                        .push(GitRepository::open(&workdir, &git_directory));
+                    self.repositories.len() - 1
+                }
+            }
+        });
+        self.directories.insert(directory.to_path_buf(), position);
+        position
+    }
+
+    // Returns the two-character status of the given file, or "--" if it is not part of a
+    // git working tree.
+    pub fn status(&mut self, file: &File) -> String {
+        // Only the parent directory is canonicalized, so that a symbolic link gets its own status
+        // rather than its target's, even if it is broken.
+        let path = match (file.full_path.parent(), file.full_path.file_name()) {
+            (Some(parent), Some(name)) if !parent.as_os_str().is_empty() => {
+                parent.canonicalize().map(|parent| parent.join(name))
+            }
+            (_, Some(name)) => Path::new(".")
+                .canonicalize()
+                .map(|parent| parent.join(name)),
+            _ => file.full_path.canonicalize(),
+        };
+        let path = match path {
+            Ok(path) => path,
+            Err(_) => return String::from("--"),
+        };
+
+        // A directory is looked up from itself, so that a repository's root is part of it.
+        let directory = match file.is_dir {
+            true => path.as_path(),
+            false => path.parent().unwrap_or(&path),
+        };
+        let position = match self.find_repository(directory) {
+            Some(position) => position,
+            None => return String::from("--"),
+        };
+
+        let repository = &mut self.repositories[position];
+        match path.strip_prefix(&repository.workdir) {
+            Ok(relative_path) => {
+                let relative_path = relative_path.to_path_buf();
+                repository.status(&relative_path, file.is_dir)
+            }
+            Err(_) => String::from("--"),
+        }
+    }
+}
+
+impl GitRepository {
+    fn open(workdir: &Path, git_directory: &Path) -> GitRepository {
+        // The "commondir" file of a linked worktree holds the path of the main git directory,
+        // relative to its own.
+        let common_directory = match read_to_string(git_directory.join("commondir")) {
+            Ok(content) => git_directory.join(content.trim_end_matches(['\n', '\r'])),
+            Err(_) => git_directory.to_path_buf(),
+        };
+        let mut repository = GitRepository {
+            workdir: workdir.to_path_buf(),
+            git_directory: git_directory.to_path_buf(),
+            common_directory,
+            index: BTreeMap::new(),
+            conflicts: Vec::new(),
+            head_tree: None,
+            head_trees: HashMap::new(),
+            packs: OnceCell::new(),
+            ignore_patterns: HashMap::new(),
+            tracked_statuses: HashMap::new(),
+            untracked_directories: HashMap::new(),
+        };
+
+        repository.read_index();
+        repository.head_tree = repository
+            .resolve_head()
+            .and_then(|commit| repository.commit_tree(&commit));
+        repository
+    }
+
+    fn read_index(&mut self) {
+        let data = match read(self.git_directory.join("index")) {
+            Ok(data) => data,
+            Err(_) => return,
+        };
+
+        for (path, entry) in parse_index(&data) {
+            if entry.stage != 0 {
+                self.conflicts.push(path.clone());
+            }
+            if entry.stage == 0 || !self.index.contains_key(&path) {
+                self.index.insert(path, entry);
+            }
+        }
+    }
+
+    // Finds the commit HEAD points to, following a symbolic reference if needed.
+    fn resolve_head(&self) -> Option<[u8; 20]> {
+        let head = read_to_string(self.git_directory.join("HEAD")).ok()?;
+        let head = head.trim();
+
+        match head.strip_prefix("ref: ") {
+            Some(reference) => self.resolve_reference(reference),
+            None => parse_hex_hash(head),
+        }
+    }
+
+    // References are looked up in the worktree's own git directory first, as some of them are
+    // specific to it, and then in the shared one.
+    fn resolve_reference(&self, reference: &str) -> Option<[u8; 20]> {
+        for directory in [&self.git_directory, &self.common_directory] {
+            if let Ok(content) = read_to_string(directory.join(reference)) {
+                return parse_hex_hash(content.trim());
+            }
+        }
+
+        let packed_references = read_to_string(self.common_directory.join("packed-refs")).ok()?;
+        for line in packed_references.lines() {
+            let mut parts = line.splitn(2, ' ');
+            if let (Some(hash), Some(name)) = (parts.next(), parts.next()) {
+                if name == reference {
+                    return parse_hex_hash(hash);
+                }
+            }
+        }
+        None
+    }
+
+    fn commit_tree(&self, commit: &[u8; 20]) -> Option<[u8; 20]> {
+        let (object_type, content) = self.read_object(commit)?;
+        if object_type != "commit" {
+            return None;
+        }
+        let content = String::from_utf8_lossy(&content);
+        let tree = content.lines().next()?.strip_prefix("tree ")?;
+        parse_hex_hash(tree)
+    }
+
+    // Returns the hash and mode of a path in the HEAD tree, so staged changes can be detected.
+    // Only the trees of the directories leading to the path are read, each one once.
+    fn head_entry(&mut self, relative_path: &Path) -> Option<([u8; 20], u32)> {
+        let mut tree = self.head_tree?;
+        let mut directory = PathBuf::new();
+        let components: Vec<&OsStr> = relative_path.iter().collect();
+
+        for (depth, component) in components.iter().enumerate() {
+            if !self.head_trees.contains_key(&directory) {
+                let entries = self.read_tree(&tree);
+                self.head_trees.insert(directory.clone(), entries);
+            }
+            let (hash, mode) = *self.head_trees[&directory].get(*component)?;
+            if depth + 1 == components.len() {
+                return Some((hash, mode));
+            }
+            if mode != 0o40000 {
+                return None;
+            }
+            tree = hash;
+            directory.push(component);
+        }
+        None
+    }
+
+    fn read_tree(&self, tree: &[u8; 20]) -> TreeEntries {
+        match self.read_object(tree) {
+            Some((object_type, content)) if object_type == "tree" => parse_tree(&content),
+            _ => HashMap::new(),
+        }
+    }
+
+    fn packs(&self) -> &Vec<Pack> {
+        self.packs
+            .get_or_init(|| read_pack_indexes(&self.common_directory.join("objects").join("pack")))
+    }
+
+    // Reads an object from the loose object store or, failing that, from the pack files.
+    fn read_object(&self, hash: &[u8; 20]) -> Option<(String, Vec<u8>)> {
+        let hex = to_hex(hash);
+        let loose_path = self
+            .common_directory
+            .join("objects")
+            .join(&hex[0..2])
+            .join(&hex[2..]);
+
+        if let Ok(compressed) = read(loose_path) {
+            let data = inflate(&compressed)?;
+            let header_end = data.iter().position(|&byte| byte == 0)?;
+            let header = String::from_utf8_lossy(&data[..header_end]);
+            let object_type = header.split(' ').next()?.to_string();
+            return Some((object_type, data[header_end + 1..].to_vec()));
+        }
+
+        for pack in self.packs() {
+            if let Some(offset) = find_in_pack_index(&pack.index, hash) {
+                let pack_data = pack
+                    .data
+                    .get_or_init(|| read(&pack.path).unwrap_or_default());
+                let (object_type, content) = self.read_packed_object(pack_data, offset)?;
+                return Some((packed_type_name(object_type)?.to_string(), content));
+            }
+        }
+        None
+    }
+
+    // Reads the object at the given offset of a pack file, applying deltas when needed.
+    fn read_packed_object(&self, pack: &[u8], offset: usize) -> Option<(u8, Vec<u8>)> {
+        let mut position = offset;
+        let mut byte = *pack.get(position)?;
+        let object_type = (byte >> 4) & 0x7;
+        position += 1;
+        while byte & 0x80 != 0 {
+            byte = *pack.get(position)?;
+            position += 1;
+        }
+
+        match object_type {
+            6 => {
+                let (distance, length) = read_offset_varint(pack.get(position..)?);
+                position += length;
+                let (base_type, base) =
+                    self.read_packed_object(pack, offset.checked_sub(distance as usize)?)?;
+                let delta = inflate(pack.get(position..)?)?;
+                Some((base_type, apply_delta(&base, &delta)?))
+            }
+            7 => {
+                let mut base_hash = [0; 20];
+                base_hash.copy_from_slice(pack.get(position..position + 20)?);
+                position += 20;
+                let (base_type, base) = self.read_object(&base_hash)?;
+                let delta = inflate(pack.get(position..)?)?;
+                let base_type = match base_type.as_str() {
+                    "commit" => 1,
+                    "tree" => 2,
+                    "blob" => 3,
+                    _ => 4,
+                };
+                Some((base_type, apply_delta(&base, &delta)?))
+            }
+            _ => Some((object_type, inflate(pack.get(position..)?)?)),
+        }
+    }
+
+    // Checks the .gitignore files from the root of the working tree down to the file's
+    // directory, as well as .git/info/exclude. The last matching pattern wins.
+    fn is_ignored(&mut self, relative_path: &Path, is_dir: bool) -> bool {
+        let components: Vec<&OsStr> = relative_path.iter().collect();
+
+        for depth in 0..components.len() {
+            let mut directory = PathBuf::new();
+            let mut ignored = false;
+            let candidate: PathBuf = components[..=depth].iter().collect();
+            let candidate_is_dir = depth + 1 < components.len() || is_dir;
+
+            for (level, component) in components[..=depth].iter().enumerate() {
+                let patterns = self.ignore_patterns_for(&directory);
+                let path_in_directory = candidate.strip_prefix(&directory).unwrap_or(&candidate);
+                for pattern in patterns {
+                    if pattern.matches(path_in_directory, candidate_is_dir) {
+                        ignored = !pattern.negated;
+                    }
+                }
+                if level < depth {
+                    directory.push(component);
+                }
+            }
+
+            // Once a directory is ignored, git does not look at anything inside of it.
+            if ignored {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn ignore_patterns_for(&mut self, directory: &Path) -> &Vec<IgnorePattern> {
+        if !self.ignore_patterns.contains_key(directory) {
+            let mut patterns = Vec::new();
+            if directory.as_os_str().is_empty() {
+                let exclude = self.common_directory.join("info").join("exclude");
+                patterns.append(&mut parse_ignore_file(&exclude));
+            }
+            patterns.append(&mut parse_ignore_file(
+                &self.workdir.join(directory).join(".gitignore"),
+            ));
+            self.ignore_patterns
+                .insert(directory.to_path_buf(), patterns);
+        }
+        &self.ignore_patterns[directory]
+    }
+
+    // Compares a tracked file's index entry with the HEAD tree (first character) and with the
+    // worktree (second character).
+    fn tracked_file_status(&mut self, relative_path: &Path) -> (char, char) {
+        if let Some(status) = self.tracked_statuses.get(relative_path) {
+            return *status;
+        }
+        let head_entry = self.head_entry(relative_path);
+        let entry = &self.index[relative_path];
+        let absolute_path = self.workdir.join(relative_path);
+
+        let staged = match head_entry {
+            None => 'N',
+            Some((hash, mode)) => {
+                if hash != entry.hash {
+                    'M'
+                } else if mode != entry.mode {
+                    'T'
+                } else {
+                    '-'
+                }
+            }
+        };
+
+        let unstaged = match absolute_path.symlink_metadata() {
+            Err(_) => 'D',
+            Ok(metadata) => {
+                if entry.mode & 0o170000 == 0o160000 {
+                    // Submodules are listed as directories, their content is not compared.
+                    '-'
+                } else if metadata.mode() & 0o170000 != entry.mode & 0o170000 {
+                    'T'
+                } else if (metadata.is_file() && metadata.mode() & 0o100 != entry.mode & 0o100)
+                    || metadata.size() as u32 != entry.size
+                {
+                    // Like git, only the owner's execute permission is compared.
+                    'M'
+                } else if metadata.mtime() as u32 == entry.mtime_seconds
+                    && metadata.mtime_nsec() as u32 == entry.mtime_nanoseconds
+                {
+                    '-'
+                } else if hash_worktree_file(&absolute_path, &metadata) == Some(entry.hash) {
+                    // The stat data changed (e.g. after a touch), but the content did not.
+                    '-'
+                } else {
+                    'M'
+                }
+            }
+        };
+
+        self.tracked_statuses
+            .insert(relative_path.to_path_buf(), (staged, unstaged));
+        (staged, unstaged)
+    }
+
+    // Checks whether a directory contains files that are neither tracked nor ignored. Like git,
+    // empty directories don't count, while a repository nested in the working tree does.
+    fn contains_untracked_files(&mut self, directory: &Path) -> bool {
+        if let Some(contains_untracked_files) = self.untracked_directories.get(directory) {
+            return *contains_untracked_files;
+        }
+        let entries = match read_dir(self.workdir.join(directory)) {
+            Ok(entries) => entries,
+            Err(_) => return false,
+        };
+
+        let mut contains_untracked_files = false;
+        for entry in entries.flatten() {
+            if entry.file_name() == ".git" {
+                continue;
+            }
+            let path = directory.join(entry.file_name());
+            let is_dir = entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+            // Submodules are in the index like files.
+            if self.index.contains_key(&path) || self.is_ignored(&path, is_dir) {
+                continue;
+            }
+            if is_dir == false
+                || self.workdir.join(&path).join(".git").exists()
+                || self.contains_untracked_files(&path)
+            {
+                contains_untracked_files = true;
+                break;
+            }
+        }
+        self.untracked_directories
+            .insert(directory.to_path_buf(), contains_untracked_files);
+        contains_untracked_files
+    }
+
+    // Returns the two-character status for a path relative to the working tree.
+    fn status(&mut self, relative_path: &Path, is_dir: bool) -> String {
+        if relative_path.starts_with(".git") {
+            return String::from("--");
+        }
+        if self
+            .conflicts
+            .iter()
+            .any(|path| path.starts_with(relative_path))
+        {
+            return String::from("UU");
+        }
+        if self.index.contains_key(relative_path) {
+            let (staged, unstaged) = self.tracked_file_status(relative_path);
+            return format!("{}{}", staged, unstaged);
+        }
+
+        if is_dir {
+            // A directory takes the most significant status of the tracked files it contains, an
+            // untracked file inside of it counting as a new file in the worktree.
+            let tracked: Vec<PathBuf> = self
+                .index
+                .range(relative_path.to_path_buf()..)
+                .map(|(path, _)| path)
+                .take_while(|path| path.starts_with(relative_path))
+                .cloned()
+                .collect();
+            if !tracked.is_empty() {
+                let mut staged = '-';
+                let mut unstaged = '-';
+                for path in tracked {
+                    let (file_staged, file_unstaged) = self.tracked_file_status(&path);
+                    staged = most_significant_status(staged, file_staged);
+                    unstaged = most_significant_status(unstaged, file_unstaged);
+                }
+                if self.contains_untracked_files(relative_path) {
+                    unstaged = most_significant_status(unstaged, 'N');
+                }
+                return format!("{}{}", staged, unstaged);
+            }
+        }
+
+        if self.is_ignored(relative_path, is_dir) {
+            String::from("-I")
+        } else {
+            String::from("-N")
+        }
+    }
+}
+
+impl IgnorePattern {
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        if self.directory_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            glob_match(&self.pattern, path.as_os_str().as_bytes())
+        } else {
+            match path.file_name() {
+                Some(name) => glob_match(&self.pattern, name.as_bytes()),
+                None => false,
+            }
+        }
+    }
+}
+
+// Walks up from the given directory until one containing a ".git" entry is found, returning it
+// along with its git directory. ".git" is usually the git directory itself, but linked worktrees
+// and submodules have a ".git" file instead, whose "gitdir: <path>" line points to it.
+fn find_git_directory(directory: &Path) -> Option<(PathBuf, PathBuf)> {
+    for ancestor in directory.ancestors() {
+        let dot_git = ancestor.join(".git");
+        if dot_git.is_dir() {
+            return Some((ancestor.to_path_buf(), dot_git));
+        }
+        if dot_git.is_file() {
+            let content = read_to_string(&dot_git).ok()?;
+            let git_directory = content.lines().next()?.strip_prefix("gitdir: ")?;
+            return Some((ancestor.to_path_buf(), ancestor.join(git_directory)));
+        }
+    }
+    None
+}
+
+fn parse_ignore_file(path: &Path) -> Vec<IgnorePattern> {
+    match read(path) {
+        Ok(content) => parse_ignore_patterns(&content),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn parse_ignore_patterns(content: &[u8]) -> Vec<IgnorePattern> {
+    let mut patterns = Vec::new();
+    for line in content.split(|&byte| byte == b'\n') {
+        let mut line = line.to_vec();
+        while line.last() == Some(&b'\r') || (line.last() == Some(&b' ') && !line.ends_with(b"\\ "))
+        {
+            line.pop();
+        }
+        if line.is_empty() || line[0] == b'#' {
+            continue;
+        }
+
+        let negated = line[0] == b'!';
+        if negated {
+            line.remove(0);
+        }
+        let directory_only = line.last() == Some(&b'/');
+        if directory_only {
+            line.pop();
+        }
+        let anchored = line.contains(&b'/');
+        if line.first() == Some(&b'/') {
+            line.remove(0);
+        }
+        if line.is_empty() {
+            continue;
+        }
+
+        patterns.push(IgnorePattern {
+            pattern: line,
+            negated,
+            directory_only,
+            anchored,
+        });
+    }
+    patterns
+}
+
+// Parses the content of .git/index (versions 2, 3 and 4), stopping at the first entry that is
+// truncated. Extensions at the end of the file are ignored.
+fn parse_index(data: &[u8]) -> Vec<(PathBuf, IndexEntry)> {
+    let mut entries = Vec::new();
+    if data.len() < 12 || &data[0..4] != b"DIRC" {
+        return entries;
+    }
+
+    let version = read_u32(data, 4);
+    let number_of_entries = read_u32(data, 8);
+    let mut offset = 12;
+    let mut previous_name: Vec<u8> = Vec::new();
+
+    for _ in 0..number_of_entries {
+        if offset + 62 > data.len() {
+            break;
+        }
+        let entry_start = offset;
+        let mut hash = [0; 20];
+        hash.copy_from_slice(&data[offset + 40..offset + 60]);
+        let flags = read_u16(data, offset + 60);
+        let entry = IndexEntry {
+            mtime_seconds: read_u32(data, offset + 8),
+            mtime_nanoseconds: read_u32(data, offset + 12),
+            mode: read_u32(data, offset + 24),
+            size: read_u32(data, offset + 36),
+            hash,
+            stage: (flags >> 12) & 0x3,
+        };
+        offset += 62;
+        if version >= 3 && flags & 0x4000 != 0 {
+            offset += 2;
+        }
+        // The extended flags and the name may be missing from a truncated index.
+        let rest = match data.get(offset..) {
+            Some(rest) => rest,
+            None => break,
+        };
+
+        let name = if version >= 4 {
+            let (strip_length, length) = read_offset_varint(rest);
+            let rest = &rest[length..];
+            let end = match rest.iter().position(|&byte| byte == 0) {
+                Some(end) => end,
+                None => break,
+            };
+            let keep = previous_name.len().saturating_sub(strip_length as usize);
+            let mut name = previous_name[..keep].to_vec();
+            name.extend_from_slice(&rest[..end]);
+            offset += length + end + 1;
+            name
+        } else {
+            let end = match rest.iter().position(|&byte| byte == 0) {
+                Some(end) => offset + end,
+                None => break,
+            };
+            let name = data[offset..end].to_vec();
+            // Entries are padded with 1 to 8 NUL bytes to a multiple of 8 bytes.
+            offset = entry_start + ((end - entry_start + 8) & !7);
+            name
+        };
+
+        entries.push((PathBuf::from(OsStr::from_bytes(&name)), entry));
+        previous_name = name;
+    }
+    entries
+}
+
+// Orders the status letters so that a directory shows the most relevant change it contains.
+fn most_significant_status(current: char, new: char) -> char {
+    let rank = |status: char| match status {
+        'M' => 4,
+        'N' | 'D' => 3,
+        'T' => 2,
+        '-' => 0,
+        _ => 1,
+    };
+    if rank(new) > rank(current) {
+        new
+    } else {
+        current
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+// Like read_u32(), returning None if the data ends before the number does.
+fn get_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([data[offset], data[offset + 1]])
+}
+
+// Decodes the variable-length integer git uses for index v4 path prefixes and pack offsets.
+// Returns the value and the number of bytes it took.
+fn read_offset_varint(data: &[u8]) -> (u64, usize) {
+    let mut length = 0;
+    let mut byte = match data.first() {
+        Some(byte) => *byte,
+        None => return (0, 0),
+    };
+    let mut value = (byte & 0x7f) as u64;
+    length += 1;
+
+    while byte & 0x80 != 0 && length < data.len() {
+        byte = data[length];
+        length += 1;
+        value = ((value + 1) << 7) | (byte & 0x7f) as u64;
+    }
+    (value, length)
+}
+
+// Decodes the little-endian variable-length sizes found at the start of a delta.
+fn read_size_varint(data: &[u8], position: &mut usize) -> Option<usize> {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *data.get(*position)?;
+        *position += 1;
+        value |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+}
+
+// Rebuilds an object from its base and a delta made of "copy" and "insert" instructions.
+fn apply_delta(base: &[u8], delta: &[u8]) -> Option<Vec<u8>> {
+    let mut position = 0;
+    read_size_varint(delta, &mut position)?;
+    let result_size = read_size_varint(delta, &mut position)?;
+    let mut result = Vec::with_capacity(result_size);
+
+    while position < delta.len() {
+        let instruction = delta[position];
+        position += 1;
+
+        if instruction & 0x80 != 0 {
+            let mut copy_offset = 0;
+            let mut copy_size = 0;
+            for bit in 0..4 {
+                if instruction & (1 << bit) != 0 {
+                    copy_offset |= (*delta.get(position)? as usize) << (8 * bit);
+                    position += 1;
+                }
+            }
+            for bit in 0..3 {
+                if instruction & (0x10 << bit) != 0 {
+                    copy_size |= (*delta.get(position)? as usize) << (8 * bit);
+                    position += 1;
+                }
+            }
+            if copy_size == 0 {
+                copy_size = 0x10000;
+            }
+            result.extend_from_slice(base.get(copy_offset..copy_offset + copy_size)?);
+        } else if instruction != 0 {
+            let size = instruction as usize;
+            result.extend_from_slice(delta.get(position..position + size)?);
+            position += size;
+        } else {
+            return None;
+        }
+    }
+    Some(result)
+}
+
+// Parses the entries of a tree object, each one being "<mode> <name>\0<20-byte hash>".
+fn parse_tree(content: &[u8]) -> TreeEntries {
+    let mut entries = HashMap::new();
+    let mut offset = 0;
+
+    while offset < content.len() {
+        let space = match content[offset..].iter().position(|&byte| byte == b' ') {
+            Some(space) => offset + space,
+            None => break,
+        };
+        let nul = match content[space..].iter().position(|&byte| byte == 0) {
+            Some(nul) => space + nul,
+            None => break,
+        };
+        if nul + 21 > content.len() {
+            break;
+        }
+        let mode =
+            u32::from_str_radix(&String::from_utf8_lossy(&content[offset..space]), 8).unwrap_or(0);
+        let name = OsStr::from_bytes(&content[space + 1..nul]).to_os_string();
+        let mut hash = [0; 20];
+        hash.copy_from_slice(&content[nul + 1..nul + 21]);
+        entries.insert(name, (hash, mode));
+        offset = nul + 21;
+    }
+    entries
+}
+
+// Reads the index of every pack of the repository. The packs themselves are read on demand.
+fn read_pack_indexes(pack_directory: &Path) -> Vec<Pack> {
+    let entries = match read_dir(pack_directory) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut packs = Vec::new();
+    for entry in entries.flatten() {
+        let index_path = entry.path();
+        if index_path.extension() != Some(OsStr::new("idx")) {
+            continue;
+        }
+        if let Ok(index) = read(&index_path) {
+            packs.push(Pack {
+                index,
+                path: index_path.with_extension("pack"),
+                data: OnceCell::new(),
+            });
+        }
+    }
+    packs
+}
+
+// Looks the hash up in a version 2 pack index, returning the object's offset in the pack. The
+// index may be truncated or corrupt, so every read past the fanout table is checked.
+fn find_in_pack_index(index: &[u8], hash: &[u8; 20]) -> Option<usize> {
+    if index.len() < 8 + 256 * 4 || &index[0..4] != b"\xfftOc" || read_u32(index, 4) != 2 {
+        return None;
+    }
+
+    let fanout = |byte: usize| read_u32(index, 8 + byte * 4) as usize;
+    let number_of_objects = fanout(255);
+    let start = if hash[0] == 0 {
+        0
+    } else {
+        fanout(hash[0] as usize - 1)
+    };
+    let end = fanout(hash[0] as usize);
+    let hashes_offset = 8 + 256 * 4;
+
+    let mut low = start;
+    let mut high = end;
+    while low < high {
+        let middle = (low + high) / 2;
+        let candidate = index.get(hashes_offset + middle * 20..hashes_offset + middle * 20 + 20)?;
+        match candidate.cmp(&hash[..]) {
+            std::cmp::Ordering::Equal => {
+                let offsets = hashes_offset + number_of_objects * 24;
+                let offset = get_u32(index, offsets + middle * 4)?;
+                if offset & 0x8000_0000 == 0 {
+                    return Some(offset as usize);
+                }
+                let large_offsets = offsets + number_of_objects * 4;
+                let large_index = large_offsets + (offset & 0x7fff_ffff) as usize * 8;
+                let high_bits = get_u32(index, large_index)? as u64;
+                let low_bits = get_u32(index, large_index + 4)? as u64;
+                return Some(((high_bits << 32) | low_bits) as usize);
+            }
+            std::cmp::Ordering::Less => low = middle + 1,
+            std::cmp::Ordering::Greater => high = middle,
+        }
+    }
+    None
+}
+
+fn packed_type_name(object_type: u8) -> Option<&'static str> {
+    match object_type {
+        1 => Some("commit"),
+        2 => Some("tree"),
+        3 => Some("blob"),
+        4 => Some("tag"),
+        _ => None,
+    }
+}
+
+fn inflate(data: &[u8]) -> Option<Vec<u8>> {
+    let mut decoder = ZlibDecoder::new(data);
+    let mut result = Vec::new();
+    decoder.read_to_end(&mut result).ok()?;
+    Some(result)
+}
+
+fn parse_hex_hash(hex: &str) -> Option<[u8; 20]> {
+    if hex.len() != 40 {
+        return None;
+    }
+    let mut hash = [0; 20];
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(hash)
+}
+
+fn to_hex(hash: &[u8; 20]) -> String {
+    hash.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Computes the blob hash git would give to the file (or symbolic link) in the worktree.
+fn hash_worktree_file(path: &Path, metadata: &std::fs::Metadata) -> Option<[u8; 20]> {
+    let content = if metadata.file_type().is_symlink() {
+        std::fs::read_link(path)
+            .ok()?
+            .as_os_str()
+            .as_bytes()
+            .to_vec()
+    } else {
+        read(path).ok()?
+    };
+
+    let mut object = format!("blob {}\0", content.len()).into_bytes();
+    object.extend_from_slice(&content);
+    Some(sha1(&object))
+}
+
+// Plain SHA-1, only used to compare worktree content with the hashes stored in the index.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for chunk in message.chunks(64) {
+        let mut words = [0u32; 80];
+        for i in 0..16 {
+            words[i] = u32::from_be_bytes([
+                chunk[i * 4],
+                chunk[i * 4 + 1],
+                chunk[i * 4 + 2],
+                chunk[i * 4 + 3],
+            ]);
+        }
+        for i in 16..80 {
+            words[i] = (words[i - 3] ^ words[i - 8] ^ words[i - 14] ^ words[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, word) in words.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temporary = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temporary;
+        }
+
+        state[0] = state[0].wrapping_add(a);
+        state[1] = state[1].wrapping_add(b);
+        state[2] = state[2].wrapping_add(c);
+        state[3] = state[3].wrapping_add(d);
+        state[4] = state[4].wrapping_add(e);
+    }
+
+    let mut hash = [0; 20];
+    for (i, word) in state.iter().enumerate() {
+        hash[i * 4..i * 4 + 4].copy_from_slice(&word.to_be_bytes());
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn get_empty_repository() -> GitRepository {
+        GitRepository::open(Path::new("/nonexistent"), Path::new("/nonexistent/.git"))
+    }
+
+    fn deflate(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    // Builds an index file of the given version. Each entry is a name and its stage, its size
+    // being the length of the name and its hash made of its first byte.
+    fn get_index(version: u32, entries: &[(&str, u16)], extended: bool) -> Vec<u8> {
+        let mut data = b"DIRC".to_vec();
+        data.extend_from_slice(&version.to_be_bytes());
+        data.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+        let mut previous_name = "";
+
+        for (name, stage) in entries {
+            let entry_start = data.len();
+            let mut fields = [0u32; 10];
+            fields[2] = 1_700_000_000;
+            fields[3] = 123;
+            fields[6] = 0o100644;
+            fields[9] = name.len() as u32;
+            for field in fields {
+                data.extend_from_slice(&field.to_be_bytes());
+            }
+            data.extend_from_slice(&[name.as_bytes()[0]; 20]);
+            let mut flags = (stage << 12) | name.len() as u16;
+            if extended == true {
+                flags |= 0x4000;
+            }
+            data.extend_from_slice(&flags.to_be_bytes());
+            if extended == true {
+                data.extend_from_slice(&[0, 0]);
+            }
+
+            if version >= 4 {
+                let common = previous_name
+                    .bytes()
+                    .zip(name.bytes())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                data.push((previous_name.len() - common) as u8);
+                data.extend_from_slice(&name.as_bytes()[common..]);
+                data.push(0);
+            } else {
+                data.extend_from_slice(name.as_bytes());
+                data.push(0);
+                while (data.len() - entry_start).is_multiple_of(8) == false {
+                    data.push(0);
+                }
+            }
+            previous_name = name;
+        }
+        data
+    }
+
+    fn get_names(entries: &[(PathBuf, IndexEntry)]) -> Vec<PathBuf> {
+        entries.iter().map(|(path, _)| path.clone()).collect()
+    }
+
+    #[test]
+    fn sha1_matches_the_known_vectors() {
+        assert_eq!(
+            to_hex(&sha1(b"")),
+            "da39a3ee5e6b4b0d3255bfef95601890afd80709"
+        );
+        assert_eq!(
+            to_hex(&sha1(b"abc")),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        // Two blocks, as the padding doesn't fit after 56 bytes.
+        assert_eq!(
+            to_hex(&sha1(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+        );
+        // The hash "git hash-object" gives to a file containing "hello\n".
+        assert_eq!(
+            to_hex(&sha1(b"blob 6\0hello\n")),
+            "ce013625030ba8dba906f756967f9e9ca394464a"
+        );
+    }
+
+    #[test]
+    fn index_entries_are_parsed_in_every_version() {
+        let names = ["a.txt", "dir/file1", "dir/file2", "other"];
+        let entries: Vec<(&str, u16)> = names.iter().map(|name| (*name, 0)).collect();
+        let expected: Vec<PathBuf> = names.iter().map(PathBuf::from).collect();
+
+        for (version, extended) in [(2, false), (3, false), (3, true), (4, false), (4, true)] {
+            let parsed = parse_index(&get_index(version, &entries, extended));
+
+            assert_eq!(get_names(&parsed), expected);
+            let (_, entry) = &parsed[1];
+            assert_eq!(entry.mtime_seconds, 1_700_000_000);
+            assert_eq!(entry.mtime_nanoseconds, 123);
+            assert_eq!(entry.mode, 0o100644);
+            assert_eq!(entry.size, 9);
+            assert_eq!(entry.hash, [b'd'; 20]);
+            assert_eq!(entry.stage, 0);
+        }
+    }
+
+    #[test]
+    fn conflicted_index_entries_keep_their_stage() {
+        let data = get_index(2, &[("file", 1), ("file", 2), ("file", 3)], false);
+        let stages: Vec<u16> = parse_index(&data)
+            .iter()
+            .map(|(_, entry)| entry.stage)
+            .collect();
+
+        assert_eq!(stages, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn truncated_indexes_are_parsed_up_to_the_last_complete_entry() {
+        let entries = [("a.txt", 0), ("dir/file1", 0), ("dir/file2", 0)];
+
+        for (version, extended) in [(2, false), (3, true), (4, false), (4, true)] {
+            let data = get_index(version, &entries, extended);
+            let names = get_names(&parse_index(&data));
+            for length in 0..data.len() {
+                let truncated_names = get_names(&parse_index(&data[..length]));
+                assert!(names.starts_with(&truncated_names));
+            }
+            // Without the end of the last name, only the first entries are left.
+            let last_name_end = data.len() - data.iter().rev().position(|&byte| byte != 0).unwrap();
+            assert_eq!(parse_index(&data[..last_name_end]).len(), entries.len() - 1);
+        }
+    }
+
+    #[test]
+    fn deltas_copy_from_the_base_and_insert_new_data() {
+        let base = b"hello world";
+        // Both sizes, "hello " copied from offset 0, "there" inserted, then "world" copied from
+        // offset 6 with its offset and size given by one byte each.
+        let mut delta = vec![11, 16, 0x90, 6, 5];
+        delta.extend_from_slice(b"there");
+        delta.extend_from_slice(&[0x91, 6, 5]);
+
+        assert_eq!(
+            apply_delta(base, &delta),
+            Some(b"hello thereworld".to_vec())
+        );
+        // A copy going past the end of the base is rejected.
+        assert_eq!(apply_delta(base, &[11, 20, 0x91, 6, 20]), None);
+    }
+
+    #[test]
+    fn offset_deltas_are_resolved_in_packs() {
+        let base = b"hello world";
+        let mut delta = vec![11, 11, 0x90, 6];
+        delta.push(5);
+        delta.extend_from_slice(b"there");
+
+        // A blob of 11 bytes at offset 12, right after the pack header, then a delta of 11 bytes
+        // whose base is that blob.
+        let mut pack = b"PACK\0\0\0\x02\0\0\0\x02".to_vec();
+        pack.push((3 << 4) | 11);
+        pack.extend_from_slice(&deflate(base));
+        let delta_offset = pack.len();
+        pack.push((6 << 4) | delta.len() as u8);
+        pack.push((delta_offset - 12) as u8);
+        pack.extend_from_slice(&deflate(&delta));
+
+        let repository = get_empty_repository();
+        assert_eq!(
+            repository.read_packed_object(&pack, 12),
+            Some((3, base.to_vec()))
+        );
+        assert_eq!(
+            repository.read_packed_object(&pack, delta_offset),
+            Some((3, b"hello there".to_vec()))
+        );
+    }
+
+    #[test]
+    fn truncated_pack_indexes_are_rejected() {
+        let hash = [0x42; 20];
+        // A version 2 index holding a single object, whose offset is in the large offset table.
+        let mut index = b"\xfftOc\0\0\0\x02".to_vec();
+        for byte in 0..256 {
+            index.extend_from_slice(&(if byte < 0x42 { 0u32 } else { 1 }).to_be_bytes());
+        }
+        index.extend_from_slice(&hash);
+        index.extend_from_slice(&[0; 4]);
+        index.extend_from_slice(&0x8000_0000u32.to_be_bytes());
+        index.extend_from_slice(&0x1_0000_0000u64.to_be_bytes());
+        assert_eq!(find_in_pack_index(&index, &hash), Some(0x1_0000_0000));
+
+        for length in [
+            index.len() - 4,
+            index.len() - 8,
+            index.len() - 12,
+            index.len() - 16,
+        ] {
+            assert_eq!(find_in_pack_index(&index[..length], &hash), None);
+        }
+        // A large offset pointing past the end of the table.
+        let position = index.len() - 12;
+        index[position..position + 4].copy_from_slice(&0x8000_0005u32.to_be_bytes());
+        assert_eq!(find_in_pack_index(&index, &hash), None);
+    }
+
+    #[test]
+    fn gitignore_patterns_follow_the_git_rules() {
+        let mut repository = get_empty_repository();
+        repository.ignore_patterns.insert(
+            PathBuf::new(),
+            parse_ignore_patterns(
+                b"# comment\n*.log\n!keep.log\nbuild/\n/root.txt\ndocs/**/*.pdf\n\\#hash\r\n",
+            ),
+        );
+        repository
+            .ignore_patterns
+            .insert(PathBuf::from("sub"), parse_ignore_patterns(b"*.tmp\n"));
+
+        let cases = [
+            ("a.log", false, true),
+            ("sub/a.log", false, true),
+            ("keep.log", false, false),
+            ("build", true, true),
+            ("build", false, false),
+            ("sub/build/file", false, true),
+            ("root.txt", false, true),
+            ("sub/root.txt", false, false),
+            ("docs/report.pdf", false, true),
+            ("docs/a/b/report.pdf", false, true),
+            ("report.pdf", false, false),
+            ("#hash", false, true),
+            ("comment", false, false),
+            ("sub/a.tmp", false, true),
+            ("a.tmp", false, false),
+        ];
+        for (path, is_dir, is_ignored) in cases {
+            assert_eq!(
+                repository.is_ignored(Path::new(path), is_dir),
+                is_ignored,
+                "{}",
+                path
+            );
+        }
+    }
+}
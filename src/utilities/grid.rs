@@ -0,0 +1,236 @@
+#![allow(clippy::bool_comparison)]
+
+// The number of spaces between two columns of the grid.
+const COLUMN_SEPARATOR_WIDTH: usize = 2;
+
+// A file of the grid: the text printed for it (which may contain color sequences, or bytes that
+// aren't valid UTF-8), and the number of columns this text takes in the terminal.
+pub struct GridCell {
+    pub text: Vec<u8>,
+    pub width: usize,
+}
+
+// Returns the width of each column when the cells fill the columns from top to bottom, each column
+// being as wide as its widest cell. "widths" holds the width of each cell, in order.
+fn get_column_widths(widths: &[usize], number_of_rows: usize) -> Vec<usize> {
+    widths
+        .chunks(number_of_rows)
+        .map(|column| column.iter().copied().max().unwrap_or(0))
+        .collect()
+}
+
+// Finds the layout of the grid like coreutils does: as many columns as fit in the terminal are
+// used, each one only as wide as it needs to be, or a single column if even two don't fit (e.g.
+// a name wider than the terminal). Returns the number of rows and the width of each column, the
+// separator excluded.
+fn get_grid_layout(widths: &[usize], terminal_width: usize) -> (usize, Vec<usize>) {
+    if widths.is_empty() {
+        return (0, Vec::new());
+    }
+    // A column is at least one character and a separator wide.
+    let most_columns = terminal_width
+        .div_ceil(1 + COLUMN_SEPARATOR_WIDTH)
+        .min(widths.len());
+
+    for number_of_columns in (2..=most_columns).rev() {
+        let number_of_rows = widths.len().div_ceil(number_of_columns);
+        let column_widths = get_column_widths(widths, number_of_rows);
+        let line_width = column_widths.iter().sum::<usize>()
+            + COLUMN_SEPARATOR_WIDTH * (column_widths.len() - 1);
+        if line_width < terminal_width {
+            return (number_of_rows, column_widths);
+        }
+    }
+    (widths.len(), get_column_widths(widths, widths.len()))
+}
+
+// Returns the lines of the grid. The cells are read from top to bottom, then from left to right,
+// each one being padded to the width of its column followed by the separator, except the last one
+// of each line. There are no lines at all without cells.
+pub fn format_grid(cells: &[GridCell], terminal_width: usize) -> Vec<Vec<u8>> {
+    let widths: Vec<usize> = cells.iter().map(|cell| cell.width).collect();
+    let (number_of_rows, column_widths) = get_grid_layout(&widths, terminal_width);
+    let mut lines: Vec<Vec<u8>> = Vec::new();
+
+    for row in 0..number_of_rows {
+        let mut line: Vec<u8> = Vec::new();
+        for column in 0..column_widths.len() {
+            let index = column * number_of_rows + row;
+            if index >= cells.len() {
+                break;
+            }
+            line.extend_from_slice(&cells[index].text);
+
+            let is_last_of_line =
+                column + 1 == column_widths.len() || index + number_of_rows >= cells.len();
+            if is_last_of_line == false {
+                let padding = column_widths[column] + COLUMN_SEPARATOR_WIDTH - cells[index].width;
+                line.extend_from_slice(" ".repeat(padding).as_bytes());
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+    use quickcheck::{quickcheck, TestResult};
+
+    // Some narrow, wide and combining characters, so that the display width of the names differs
+    // from their length.
+    const ALPHABET: [&str; 8] = ["a", "b", "Z", "0", "-", "日", "한", "e\u{301}"];
+
+    // Turns random bytes into a file name of the alphabet, never empty and without whitespace.
+    fn get_name(seed: &Vec<u8>) -> String {
+        let mut name = String::from("n");
+        for byte in seed {
+            name.push_str(ALPHABET[*byte as usize % ALPHABET.len()]);
+        }
+        name
+    }
+
+    // Lays the names out in a grid, returning its lines.
+    fn format_names(names: &[String], terminal_width: usize) -> Vec<String> {
+        let cells: Vec<GridCell> = names
+            .iter()
+            .map(|name| GridCell {
+                text: name.clone().into_bytes(),
+                width: get_display_width(name),
+            })
+            .collect();
+
+        format_grid(&cells, terminal_width)
+            .into_iter()
+            .map(|line| String::from_utf8(line).unwrap())
+            .collect()
+    }
+
+    // Reads the names back from the lines, from top to bottom and then from left to right.
+    fn read_grid(lines: &[String]) -> Vec<String> {
+        let rows: Vec<Vec<&str>> = lines
+            .iter()
+            .map(|line| line.split_whitespace().collect())
+            .collect();
+        let number_of_columns = rows.first().map_or(0, |row| row.len());
+        let mut names: Vec<String> = Vec::new();
+
+        for column in 0..number_of_columns {
+            for row in &rows {
+                if let Some(name) = row.get(column) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names
+    }
+
+    // Returns the position, in terminal columns, where each name of a line starts.
+    fn get_name_starts(line: &str) -> Vec<usize> {
+        let mut starts: Vec<usize> = Vec::new();
+        let mut previous_is_space = true;
+
+        for (index, character) in line.char_indices() {
+            if character != ' ' && previous_is_space == true {
+                starts.push(get_display_width(&line[..index]));
+            }
+            previous_is_space = character == ' ';
+        }
+        starts
+    }
+
+    quickcheck! {
+        fn every_name_is_printed_once_in_order(seeds: Vec<Vec<u8>>, terminal_width: u16) -> bool {
+            let names: Vec<String> = seeds.iter().map(get_name).collect();
+            let lines = format_names(&names, terminal_width as usize);
+
+            read_grid(&lines) == names
+        }
+
+        fn lines_fit_unless_they_hold_a_single_name(
+            seeds: Vec<Vec<u8>>,
+            terminal_width: u16
+        ) -> bool {
+            let names: Vec<String> = seeds.iter().map(get_name).collect();
+            let lines = format_names(&names, terminal_width as usize);
+
+            lines.iter().all(|line| {
+                get_display_width(line) < terminal_width as usize
+                    || line.split_whitespace().count() == 1
+            })
+        }
+
+        fn lines_have_no_trailing_spaces(seeds: Vec<Vec<u8>>, terminal_width: u16) -> bool {
+            let names: Vec<String> = seeds.iter().map(get_name).collect();
+            let lines = format_names(&names, terminal_width as usize);
+
+            lines.iter().all(|line| !line.ends_with(' '))
+        }
+
+        fn columns_are_aligned(seeds: Vec<Vec<u8>>, terminal_width: u16) -> TestResult {
+            let names: Vec<String> = seeds.iter().map(get_name).collect();
+            if names.is_empty() {
+                return TestResult::discard();
+            }
+            let widths: Vec<usize> = names.iter().map(get_display_width).collect();
+            let (_, column_widths) = get_grid_layout(&widths, terminal_width as usize);
+
+            // Each name starts where the previous columns and their separators end.
+            let lines = format_names(&names, terminal_width as usize);
+            let is_aligned = lines.iter().all(|line| {
+                get_name_starts(line).iter().enumerate().all(|(column, start)| {
+                    let expected_start: usize = column_widths[..column]
+                        .iter()
+                        .map(|width| width + COLUMN_SEPARATOR_WIDTH)
+                        .sum();
+                    *start == expected_start
+                })
+            });
+            TestResult::from_bool(is_aligned)
+        }
+    }
+
+    #[test]
+    fn no_files_give_no_lines() {
+        assert!(format_grid(&Vec::new(), 80).is_empty());
+        assert_eq!(get_grid_layout(&Vec::new(), 0), (0, Vec::new()));
+    }
+
+    #[test]
+    fn a_name_wider_than_the_terminal_gives_a_single_column() {
+        let names = vec![String::from("a"), "b".repeat(100), String::from("c")];
+
+        assert_eq!(
+            format_names(&names, 80),
+            vec![String::from("a"), "b".repeat(100), String::from("c")]
+        );
+    }
+
+    #[test]
+    fn narrow_terminals_give_a_single_column() {
+        let names = vec![String::from("a"), String::from("b")];
+
+        for terminal_width in 0..=4 {
+            assert_eq!(
+                format_names(&names, terminal_width),
+                vec![String::from("a"), String::from("b")]
+            );
+        }
+        assert_eq!(format_names(&names, 5), vec![String::from("a  b")]);
+    }
+
+    #[test]
+    fn columns_only_take_the_width_they_need() {
+        let names: Vec<String> = ["a", "bb", "c", "dddddd", "e"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+
+        assert_eq!(
+            format_names(&names, 14),
+            vec![String::from("a   c       e"), String::from("bb  dddddd")]
+        );
+    }
+}
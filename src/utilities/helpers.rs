@@ -1,8 +1,111 @@
+#![allow(clippy::bool_comparison, clippy::ptr_arg)]
+
 use crate::*;
-use std::fs::read_link;
-use std::os::unix::fs::PermissionsExt;
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fs::{canonicalize, read_link};
+use std::io::{self, stdout, IsTerminal, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
 use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::thread;
+use std::time::SystemTime;
 use terminal_size::{terminal_size, Width};
+use unicode_width::UnicodeWidthStr;
+
+// The program's exit status, raised whenever a problem is encountered: 1 for minor problems, 2 for
+// serious ones, like coreutils does.
+static EXIT_STATUS: AtomicI32 = AtomicI32::new(0);
+
+// Raises the exit status of the program, keeping the most serious one.
+pub fn set_exit_status(status: i32) {
+    EXIT_STATUS.fetch_max(status, Ordering::Relaxed);
+}
+
+// Returns the status the program should exit with.
+pub fn get_exit_status() -> i32 {
+    EXIT_STATUS.load(Ordering::Relaxed)
+}
+
+// The exit statuses of report_error(): minor problems (e.g. a subdirectory that can't be read) let
+// the listing go on, serious ones concern the command line itself (e.g. a missing argument).
+pub const MINOR_PROBLEM: i32 = 1;
+pub const SERIOUS_PROBLEM: i32 = 2;
+
+// An error message and its exit status, see report_error().
+pub type ReportedError = (String, i32);
+
+thread_local! {
+    // The errors held back by collect_errors() on this thread, None when they are printed right
+    // away.
+    static COLLECTED_ERRORS: RefCell<Option<Vec<ReportedError>>> = const { RefCell::new(None) };
+}
+
+// Prints an error message to the standard error, prefixed with the program's name like coreutils
+// does (e.g. "ls: cannot access 'X': No such file or directory"), and raises the exit status.
+pub fn report_error(message: &str, status: i32) {
+    let is_collected = COLLECTED_ERRORS.with_borrow_mut(|errors| match errors {
+        Some(errors) => {
+            errors.push((message.to_string(), status));
+            true
+        }
+        None => false,
+    });
+
+    if is_collected == false {
+        eprintln!("ls: {}", message);
+        set_exit_status(status);
+    }
+}
+
+// Runs the function with the errors it reports held back, and returns them along with its result.
+// Used for the directories read by the worker threads, whose errors have to be printed along with
+// the directory rather than before the output of the ones listed first.
+pub fn collect_errors<T>(function: impl FnOnce() -> T) -> (T, Vec<ReportedError>) {
+    let previous_errors = COLLECTED_ERRORS.replace(Some(Vec::new()));
+    let result = function();
+    let errors = COLLECTED_ERRORS
+        .replace(previous_errors)
+        .unwrap_or_default();
+    (result, errors)
+}
+
+// Reports the errors held back by collect_errors(), in order.
+pub fn report_errors(errors: Vec<ReportedError>) {
+    for (message, status) in errors {
+        report_error(&message, status);
+    }
+}
+
+// Returns the description of an I/O error like strerror() does (e.g. "Permission denied"), without
+// the "(os error 13)" suffix Rust adds, to match the messages of coreutils.
+pub fn describe_io_error(error: &io::Error) -> String {
+    let description = error.to_string();
+
+    match error.raw_os_error() {
+        Some(code) => description
+            .trim_end_matches(&format!(" (os error {})", code))
+            .to_string(),
+        None => description,
+    }
+}
+
+// Reports an invalid command line and exits right away, as nothing can be listed.
+pub fn report_invalid_usage(message: &str) -> ! {
+    report_error(message, SERIOUS_PROBLEM);
+    process::exit(get_exit_status());
+}
+
+// Reports a failure to write the listing (e.g. "ls > /dev/full") and exits right away, like
+// coreutils does, as nothing more can be printed.
+pub fn report_write_error(error: io::Error) -> ! {
+    eprintln!("ls: write error: {}", describe_io_error(&error));
+    process::exit(SERIOUS_PROBLEM);
+}
 
 // Orders a vector of File objects in descending order based on the last modified date.
 pub fn rank_files_by_last_modified_date(files: &mut Vec<File>) {
@@ -14,22 +117,23 @@ pub fn reverse_rank_files_by_last_modified_date(files: &mut Vec<File>) {
     files.sort_unstable_by(|a, b| a.last_modified.partial_cmp(&b.last_modified).unwrap());
 }
 
+// Returns the last modified date of a path, None if it can't be known (e.g. the file was deleted
+// in the meantime), which ranks it as the oldest.
+fn get_last_modified_date(path: &OsStr) -> Option<SystemTime> {
+    Path::new(path)
+        .symlink_metadata()
+        .ok()
+        .and_then(|metadata| metadata.modified().ok())
+}
+
 // Orders a vector of File objects in ascending order based on the last modified date.
-pub fn rank_path_by_last_modified_date(files: &mut Vec<String>) {
-    files.sort_unstable_by(|a, b| {
-        (Path::new(b).metadata().unwrap().modified().unwrap())
-            .partial_cmp(&Path::new(&a).metadata().unwrap().modified().unwrap())
-            .unwrap()
-    });
+pub fn rank_path_by_last_modified_date(files: &mut Vec<OsString>) {
+    files.sort_by_cached_key(|file| Reverse(get_last_modified_date(file)));
 }
 
 // Orders a vector of File objects in ascending order based on the last modified date.
-pub fn reverse_rank_path_by_last_modified_date(files: &mut Vec<String>) {
-    files.sort_unstable_by(|a, b| {
-        (Path::new(a).metadata().unwrap().modified().unwrap())
-            .partial_cmp(&Path::new(&b).metadata().unwrap().modified().unwrap())
-            .unwrap()
-    });
+pub fn reverse_rank_path_by_last_modified_date(files: &mut Vec<OsString>) {
+    files.sort_by_cached_key(|file| get_last_modified_date(file));
 }
 
 // Orders a vector of File objects alphabetically based on their path_name variable.
@@ -42,105 +146,161 @@ pub fn reverse_alphabetically_rank_files(files: &mut Vec<File>) {
     files.sort_unstable_by(|a, b| b.path_name.partial_cmp(&a.path_name).unwrap());
 }
 
-// Orders a vector of strings alphabetically.
-pub fn alphabetically_rank_strings(strings: &mut Vec<String>) {
+// Orders a vector of strings alphabetically, byte by byte.
+pub fn alphabetically_rank_strings(strings: &mut Vec<OsString>) {
     strings.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
 }
 
-// Orders a vector of strings alphabetically in reverse.
-pub fn reverse_alphabetically_rank_strings(strings: &mut Vec<String>) {
+// Orders a vector of strings alphabetically in reverse, byte by byte.
+pub fn reverse_alphabetically_rank_strings(strings: &mut Vec<OsString>) {
     strings.sort_unstable_by(|a, b| b.partial_cmp(a).unwrap());
 }
 
-// Alphabetically ranks a vector of PathBuf objects.
-pub fn alphabetically_rank_path_bufs(paths: &mut Vec<PathBuf>) {
-    paths.sort_unstable_by(|a, b| get_path_name(a).partial_cmp(&get_path_name(b)).unwrap());
-}
+// Checks if the path points to a file or a directory. Symbolic links that do not point to a
+// directory (including broken ones) are considered files.
+pub fn is_file(target_path: &OsStr) -> bool {
+    let path = Path::new(target_path);
 
-// Alphabetically ranks a vector in reverse of PathBuf objects.
-pub fn reverse_alphabetically_rank_path_bufs(paths: &mut Vec<PathBuf>) {
-    paths.sort_unstable_by(|a, b| get_path_name(b).partial_cmp(&get_path_name(a)).unwrap());
+    path.is_file() || (path.is_symlink() && !path.is_dir())
 }
 
-// Ranks a vector of PathBuf objects by their last modification date.
-pub fn rank_path_bufs_by_last_modified_date(paths: &mut Vec<PathBuf>) {
-    paths.sort_unstable_by(|a, b| {
-        (b.metadata().unwrap().modified().unwrap())
-            .partial_cmp(&a.metadata().unwrap().modified().unwrap())
-            .unwrap()
-    });
+// Checks whether a file found in a directory is listed: dot files need -a (or -A, which still
+// leaves out "." and ".."), and names matching a -I pattern are left out.
+pub fn is_visible(name: &OsStr, parameters: &Parameters) -> bool {
+    if name == "." || name == ".." {
+        return parameters.include_dot_files;
+    }
+    if name.as_bytes().starts_with(b".")
+        && parameters.include_dot_files == false
+        && parameters.include_almost_all_dot_files == false
+    {
+        return false;
+    }
+    !parameters
+        .ignore_patterns
+        .iter()
+        .any(|pattern| glob_match(pattern.as_bytes(), name.as_bytes()))
 }
 
-// Ranks a vector of PathBuf objects in reverse by their last modification date.
-pub fn reverse_rank_path_bufs_by_last_modified_date(paths: &mut Vec<PathBuf>) {
-    paths.sort_unstable_by(|a, b| {
-        (a.metadata().unwrap().modified().unwrap())
-            .partial_cmp(&b.metadata().unwrap().modified().unwrap())
-            .unwrap()
-    });
+// Checks whether symbolic links given as arguments are replaced by the file they point to (-L and
+// -H parameters).
+pub fn follows_command_line_symbolic_links(parameters: &Parameters) -> bool {
+    parameters.dereference == DereferenceMode::Always
+        || parameters.dereference == DereferenceMode::CommandLine
 }
 
-// Checks if the path points to a file or a directory.
-pub fn is_file(target_path: &str) -> bool {
+// Checks if an argument should be printed as a single file rather than have its content listed.
+// Symbolic links to directories are listed as directories unless links are never followed.
+pub fn is_command_line_file(target_path: &OsStr, parameters: &Parameters) -> bool {
     let path = Path::new(target_path);
 
-    path.is_file()
+    if path.is_symlink() && parameters.dereference == DereferenceMode::Never {
+        return true;
+    }
+    is_file(target_path)
 }
 
-// Checks if the path points to a file or a directory.
-pub fn file_exists(target_path: &str) -> bool {
+// Checks if the path exists. Broken symbolic links exist, even though their target does not.
+pub fn file_exists(target_path: &OsStr) -> bool {
     let path = Path::new(target_path);
 
-    path.exists()
+    path.symlink_metadata().is_ok()
 }
 
-// Return the position in the vector of the String to look for.
-pub fn return_index_for_object(args: &mut Vec<String>, object_to_find: &String) -> usize {
+// Return the position in the vector of the argument to look for.
+pub fn return_index_for_object(args: &mut Vec<OsString>, object_to_find: &OsString) -> usize {
     args.iter().position(|x| *x == *object_to_find).unwrap()
 }
 
 // Gets the width of the terminal, so the number of columns the "ls" command outputs can be
-// calculated.
-pub fn get_terminal_width() -> Result<u16, String> {
-    let size = terminal_size();
+// calculated. Like coreutils, COLUMNS (or 80) is used if the size of the terminal can't be read,
+// and there is no width at all when the output isn't a terminal (e.g. "ls | cat").
+pub fn get_terminal_width() -> Option<usize> {
+    if stdout().is_terminal() == false {
+        return None;
+    }
 
-    match size {
-        Some((Width(w), _)) => Ok(w),
-        None => Err(format!("Unable to get terminal size")),
+    match terminal_size() {
+        Some((Width(w), _)) => Some(w as usize),
+        None => Some(
+            env::var("COLUMNS")
+                .ok()
+                .and_then(|columns| columns.parse::<usize>().ok())
+                .unwrap_or(80),
+        ),
     }
 }
 
-// Converts a vector of PathBuf objects to a vector of String objects.
-pub fn convert_path_buf_vector_to_string_vector(pathbufs: &Vec<PathBuf>) -> Vec<String> {
-    let filenames: Vec<String> = pathbufs
-        .iter()
-        .filter_map(|path| path.to_str())
-        .map(|str| str.to_owned())
-        .collect();
+// Whether the files of a listing have to be stat'ed, or if the type given by the directory entries
+// is enough: plain names, sorted by name and colored (if at all) by type only.
+pub fn needs_metadata(parameters: &Parameters) -> bool {
+    parameters.long_format == true
+        || parameters.last_modified_order == true
+        || parameters.indicator_style == IndicatorStyle::Classify
+        || parameters.dereference == DereferenceMode::Always
+        || parameters
+            .colors
+            .as_ref()
+            .is_some_and(|colors| colors.needs_metadata())
+}
 
-    filenames
+// Returns the number of CPUs available to the program, used as the default number of threads.
+pub fn get_number_of_cpus() -> usize {
+    thread::available_parallelism().map_or(1, |cpus| cpus.get())
 }
 
-// Converts a vector of String objects to a vector of File structs.
-pub fn convert_string_vector_to_file_vector(strings: Vec<String>) -> Vec<File> {
-    let files: Vec<File> = strings
+// Converts a vector of arguments (coming from the command line) to a vector of File structs.
+pub fn convert_string_vector_to_file_vector(
+    strings: Vec<OsString>,
+    parameters: &Parameters,
+) -> Vec<File> {
+    let mut files: Vec<File> = strings
         .into_iter()
         .map(|string| {
             let path = Path::new(&string);
-            File::new(
-                path.file_name().unwrap().to_str().unwrap().to_owned(),
-                path.metadata().unwrap(),
-                check_extended_attributes(&path),
-            )
+            match path.symlink_metadata() {
+                Ok(metadata) => File::new(
+                    get_path_name(&path.to_path_buf()),
+                    path,
+                    metadata,
+                    follows_command_line_symbolic_links(parameters),
+                ),
+                Err(error) => {
+                    report_error(
+                        &format!(
+                            "cannot access {}: {}",
+                            quote_name_in_message(&string),
+                            describe_io_error(&error)
+                        ),
+                        SERIOUS_PROBLEM,
+                    );
+                    File::placeholder(get_path_name(&path.to_path_buf()), path, None)
+                }
+            }
         })
         .collect();
 
+    fill_security_contexts(&mut files, parameters);
     files
 }
 
-// Returns the length of the longest path name in the "files" vector, adding 1 for spacing.
-pub fn get_column_length_single_files(files: &Vec<String>) -> usize {
-    files.iter().max_by_key(|file| file.len()).unwrap().len() + 1
+// Returns the number of columns a text takes in the terminal, which is what aligns the output
+// rather than its length in bytes: East Asian wide characters and most emoji take two columns,
+// combining characters and zero-width joiners none. Like coreutils, each byte that isn't valid
+// UTF-8 counts as one column.
+pub fn get_display_width<T: AsRef<[u8]> + ?Sized>(text: &T) -> usize {
+    text.as_ref()
+        .utf8_chunks()
+        .map(|chunk| chunk.valid().width() + chunk.invalid().len())
+        .sum()
+}
+
+// Writes bytes to the standard output as they are, for the names that aren't valid UTF-8 (see
+// quote_name()).
+pub fn print_bytes(bytes: &[u8]) {
+    if let Err(error) = stdout().write_all(bytes) {
+        report_write_error(error);
+    }
 }
 
 // Check whether or not the given file is executable.
@@ -148,31 +308,237 @@ pub fn is_executable(file: &File) -> bool {
     file.file_mode.mode() & 0o111 != 0
 }
 
+// Returns the suffix the -F, -p and --file-type parameters append to a file name, based on the
+// file's type.
+pub fn get_indicator(
+    is_dir: bool,
+    is_symbolic_link: bool,
+    is_fifo: bool,
+    is_socket: bool,
+    is_executable: bool,
+    indicator_style: &IndicatorStyle,
+) -> &'static str {
+    if *indicator_style == IndicatorStyle::None {
+        ""
+    } else if is_dir == true {
+        "/"
+    } else if *indicator_style == IndicatorStyle::Slash {
+        ""
+    } else if is_symbolic_link == true {
+        "@"
+    } else if is_fifo == true {
+        "|"
+    } else if is_socket == true {
+        "="
+    } else if is_executable == true && *indicator_style == IndicatorStyle::Classify {
+        "*"
+    } else {
+        ""
+    }
+}
+
+// Returns the suffix appended to the given file's name, see get_indicator().
+pub fn get_file_indicator(file: &File, parameters: &Parameters) -> &'static str {
+    get_indicator(
+        file.is_dir,
+        file.is_symbolic_link,
+        file.is_fifo,
+        file.is_socket,
+        is_executable(file),
+        &parameters.indicator_style,
+    )
+}
+
+// Returns the suffix of the file a symbolic link points to, which is printed after the link's
+// target in long format.
+pub fn get_symbolic_link_target_indicator(file: &File, parameters: &Parameters) -> &'static str {
+    match file.full_path.metadata() {
+        Ok(metadata) => get_indicator(
+            metadata.is_dir(),
+            false,
+            metadata.file_type().is_fifo(),
+            metadata.file_type().is_socket(),
+            metadata.permissions().mode() & 0o111 != 0,
+            &parameters.indicator_style,
+        ),
+        Err(_) => "",
+    }
+}
+
 // Returns the file name the symbolic link is pointing towards. Used for long format printing.
-pub fn get_symbolic_link(file: &File) -> String {
-    read_link(&file.path_name)
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .to_string()
+pub fn get_symbolic_link(file: &File) -> OsString {
+    match read_link(&file.full_path) {
+        Ok(target) => target.into_os_string(),
+        Err(_) => OsString::from("?"),
+    }
+}
+
+// Returns the width a file takes in the grid, including its indicator (see get_file_indicator())
+// and security context (with -Z).
+pub fn get_file_width(
+    file: &File,
+    longest_security_context: usize,
+    parameters: &Parameters,
+) -> usize {
+    get_display_width(&get_security_context_prefix(
+        file,
+        longest_security_context,
+        parameters,
+    )) + get_display_width(&quote_name(&file.path_name, parameters))
+        + get_display_width(get_file_indicator(file, parameters))
+}
+
+// Reads the given extended attribute of a file, returning None if it isn't set or can't be read.
+pub fn get_extended_attribute(file: &File, name: &str) -> Option<Vec<u8>> {
+    // xattr::get() doesn't follow symbolic links, so the target is resolved beforehand when the
+    // file describes it (see the -L parameter).
+    let path = if file.is_symbolic_link == false && file.full_path.is_symlink() {
+        canonicalize(&file.full_path).ok()?
+    } else {
+        file.full_path.clone()
+    };
+
+    xattr::get(&path, name).ok().flatten()
+}
+
+// Reads the SELinux security context of a file, stored in its "security.selinux" extended
+// attribute. Returns None if the file has none, e.g. when SELinux is disabled.
+pub fn get_security_context(file: &File) -> Option<String> {
+    let context = get_extended_attribute(file, "security.selinux")?;
+
+    Some(
+        String::from_utf8_lossy(&context)
+            .trim_end_matches('\0')
+            .to_string(),
+    )
+}
+
+// Reads the security contexts of the files if the -Z parameter is included.
+pub fn fill_security_contexts(files: &mut Vec<File>, parameters: &Parameters) {
+    if parameters.security_context == false {
+        return;
+    }
+    for file in files {
+        file.security_context = get_security_context(file);
+    }
+}
+
+// Returns the security context printed for the file, "?" if it has none.
+pub fn get_security_context_name(file: &File) -> &str {
+    file.security_context.as_deref().unwrap_or("?")
 }
 
-// Returns the length of the longest path name in the "files" vector, adding 1 for spacing.
-pub fn get_column_length(files: &Vec<File>) -> usize {
+// Finds the length of the longest security context in the vector, to align them.
+pub fn get_longest_security_context<'a>(files: impl IntoIterator<Item = &'a File>) -> usize {
     files
-        .iter()
-        .max_by_key(|file| file.path_name.len())
-        .unwrap()
-        .path_name
-        .len()
-        + 1
-}
-
-// Returns the file name of a given PathBuf object
-pub fn get_path_name(path: &PathBuf) -> String {
-    path.file_name()
-        .unwrap()
-        .to_os_string()
-        .into_string()
-        .unwrap()
+        .into_iter()
+        .map(|file| get_display_width(&get_security_context_name(file)))
+        .max()
+        .unwrap_or(0)
+}
+
+// Returns the security context printed before the file's name outside of the long format, right
+// aligned on the longest one like coreutils does, or nothing without the -Z parameter.
+pub fn get_security_context_prefix(
+    file: &File,
+    longest_security_context: usize,
+    parameters: &Parameters,
+) -> String {
+    if parameters.security_context == false {
+        return String::new();
+    }
+    let security_context = get_security_context_name(file);
+    let padding = longest_security_context.saturating_sub(get_display_width(&security_context));
+
+    format!("{}{} ", " ".repeat(padding), security_context)
+}
+
+// Returns the file name of a given PathBuf object. Paths without one ("/" or ending with "..")
+// are returned whole.
+pub fn get_path_name(path: &PathBuf) -> OsString {
+    match path.file_name() {
+        Some(name) => name.to_os_string(),
+        None => path.clone().into_os_string(),
+    }
+}
+
+// Matches a name against a shell-style wildcard pattern ("*", "?", "[...]" and "\" escapes).
+// A "*" never matches a "/", while "**" matches across directories, like in .gitignore files.
+pub fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some(b'*') => {
+            if pattern.starts_with(b"**") {
+                let rest = &pattern[2..];
+                let rest = rest.strip_prefix(b"/").unwrap_or(rest);
+                (0..=text.len()).any(|i| {
+                    (i == 0 || text[i - 1] == b'/') && glob_match(rest, &text[i..])
+                        || i == text.len() && rest.is_empty()
+                })
+            } else {
+                let rest = &pattern[1..];
+                for i in 0..=text.len() {
+                    if glob_match(rest, &text[i..]) {
+                        return true;
+                    }
+                    if i < text.len() && text[i] == b'/' {
+                        return false;
+                    }
+                }
+                false
+            }
+        }
+        Some(b'?') => match text.first() {
+            Some(&character) if character != b'/' => glob_match(&pattern[1..], &text[1..]),
+            _ => false,
+        },
+        Some(b'[') => match (text.first(), match_bracket(&pattern[1..], text.first())) {
+            (Some(_), Some((true, length))) => glob_match(&pattern[1 + length..], &text[1..]),
+            (Some(b'['), None) => glob_match(&pattern[1..], &text[1..]),
+            _ => false,
+        },
+        Some(b'\\') if pattern.len() > 1 => {
+            text.first() == Some(&pattern[1]) && glob_match(&pattern[2..], &text[1..])
+        }
+        Some(&character) => {
+            text.first() == Some(&character) && glob_match(&pattern[1..], &text[1..])
+        }
+    }
+}
+
+// Parses a "[...]" set (the opening bracket already being consumed), returning whether the
+// character belongs to it and how many pattern bytes the set takes, or None if it is not closed.
+fn match_bracket(pattern: &[u8], character: Option<&u8>) -> Option<(bool, usize)> {
+    let character = *character?;
+    let mut position = 0;
+    let negated = matches!(pattern.first(), Some(b'!') | Some(b'^'));
+    if negated {
+        position += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    while position < pattern.len() {
+        let start = pattern[position];
+        if start == b']' && !first {
+            return Some((matched != negated && character != b'/', position + 1));
+        }
+        first = false;
+
+        if position + 2 < pattern.len()
+            && pattern[position + 1] == b'-'
+            && pattern[position + 2] != b']'
+        {
+            if start <= character && character <= pattern[position + 2] {
+                matched = true;
+            }
+            position += 3;
+        } else {
+            if start == character {
+                matched = true;
+            }
+            position += 1;
+        }
+    }
+    None
 }
@@ -1,2 +1,10 @@
+pub mod attributes;
+pub mod capabilities;
+pub mod colors;
+pub mod git;
+pub mod grid;
 pub mod helpers;
+pub mod quoting;
+pub mod statx;
 pub mod structs;
+pub mod workers;
@@ -0,0 +1,440 @@
+#![allow(clippy::bool_comparison)]
+
+use crate::*;
+use std::env;
+use std::ffi::OsStr;
+use std::io::{stdout, IsTerminal};
+use std::os::unix::ffi::OsStrExt;
+
+// The characters that have a meaning for the shell anywhere in a word, so that names containing
+// them are quoted by the shell quoting styles.
+const SHELL_SPECIAL_CHARACTERS: &str = " !\"$&'()*;<=>?[\\^`|";
+
+// A part of a file name: either a character, or a byte that isn't valid UTF-8.
+enum NameUnit {
+    Character(char),
+    InvalidByte(u8),
+}
+
+// Returns the quoting style matching a value of --quoting-style (or QUOTING_STYLE), None if it is
+// not one of them.
+pub fn parse_quoting_style(value: &str) -> Option<QuotingStyle> {
+    match value {
+        "literal" => Some(QuotingStyle::Literal),
+        "shell" => Some(QuotingStyle::Shell),
+        "shell-always" => Some(QuotingStyle::ShellAlways),
+        "shell-escape" => Some(QuotingStyle::ShellEscape),
+        "shell-escape-always" => Some(QuotingStyle::ShellEscapeAlways),
+        "c" => Some(QuotingStyle::C),
+        "escape" => Some(QuotingStyle::Escape),
+        "locale" => Some(QuotingStyle::Locale),
+        _ => None,
+    }
+}
+
+// Like coreutils, the style comes from QUOTING_STYLE if it is set to a valid style. Otherwise,
+// names are quoted for the shell on a terminal, and printed as they are when piped.
+pub fn get_default_quoting_style() -> QuotingStyle {
+    match env::var("QUOTING_STYLE")
+        .ok()
+        .and_then(|value| parse_quoting_style(&value))
+    {
+        Some(quoting_style) => quoting_style,
+        None if stdout().is_terminal() => QuotingStyle::ShellEscape,
+        None => QuotingStyle::Literal,
+    }
+}
+
+// Nonprintable characters are shown as "?" on a terminal (-q parameter), and printed as they are
+// when piped (--show-control-chars parameter).
+pub fn hides_control_characters_by_default() -> bool {
+    stdout().is_terminal()
+}
+
+// Splits a name into its characters, keeping the bytes that aren't valid UTF-8 apart.
+fn get_name_units(name: &OsStr) -> Vec<NameUnit> {
+    let mut units: Vec<NameUnit> = Vec::new();
+
+    for chunk in name.as_bytes().utf8_chunks() {
+        units.extend(chunk.valid().chars().map(NameUnit::Character));
+        units.extend(
+            chunk
+                .invalid()
+                .iter()
+                .map(|byte| NameUnit::InvalidByte(*byte)),
+        );
+    }
+    units
+}
+
+// Returns the C escape sequence of a nonprintable character, e.g. "\n", or the octal value of each
+// of its bytes, e.g. "\033".
+fn escape_unit(unit: &NameUnit) -> String {
+    let character = match unit {
+        NameUnit::Character(character) => *character,
+        NameUnit::InvalidByte(byte) => return format!("\\{:03o}", byte),
+    };
+
+    match character {
+        '\x07' => String::from("\\a"),
+        '\x08' => String::from("\\b"),
+        '\x0c' => String::from("\\f"),
+        '\n' => String::from("\\n"),
+        '\r' => String::from("\\r"),
+        '\t' => String::from("\\t"),
+        '\x0b' => String::from("\\v"),
+        _ => character
+            .to_string()
+            .bytes()
+            .map(|byte| format!("\\{:03o}", byte))
+            .collect(),
+    }
+}
+
+// Returns the character itself if it is printable, None otherwise.
+fn get_printable_character(unit: &NameUnit) -> Option<char> {
+    match unit {
+        NameUnit::Character(character) if !character.is_control() => Some(*character),
+        _ => None,
+    }
+}
+
+// Returns the valid characters of a name, leaving out the bytes that aren't valid UTF-8.
+fn get_valid_characters(name: &[u8]) -> impl Iterator<Item = char> + '_ {
+    name.utf8_chunks().flat_map(|chunk| chunk.valid().chars())
+}
+
+// Used by the styles that don't escape anything: nonprintable characters are replaced by "?" with
+// the -q parameter, and printed as they are otherwise, bytes that aren't valid UTF-8 included.
+fn get_literal_name(units: &Vec<NameUnit>, parameters: &Parameters) -> Vec<u8> {
+    let mut name: Vec<u8> = Vec::new();
+
+    for unit in units {
+        match unit {
+            NameUnit::Character(character)
+                if parameters.hide_control_characters == false || !character.is_control() =>
+            {
+                name.extend_from_slice(character.encode_utf8(&mut [0; 4]).as_bytes());
+            }
+            NameUnit::InvalidByte(byte) if parameters.hide_control_characters == false => {
+                name.push(*byte);
+            }
+            _ => name.push(b'?'),
+        }
+    }
+    name
+}
+
+// Checks whether a name has to be quoted to be used as a single word by the shell. "#" and "~" are
+// only special at the beginning of a word, and "{" and "}" on their own. Like coreutils, the only
+// nonprintable characters quoted are tabs, newlines and carriage returns, and bytes that aren't
+// valid UTF-8 don't need to be quoted.
+fn needs_shell_quoting(name: &[u8]) -> bool {
+    name.is_empty()
+        || name == b"{"
+        || name == b"}"
+        || name.starts_with(b"#")
+        || name.starts_with(b"~")
+        || get_valid_characters(name).any(|character| {
+            SHELL_SPECIAL_CHARACTERS.contains(character) || "\t\n\r".contains(character)
+        })
+}
+
+// Quotes text with single quotes, single quotes themselves being written as '\''.
+fn single_quote(text: &[u8]) -> Vec<u8> {
+    let mut quoted_text = vec![b'\''];
+    for byte in text {
+        match byte {
+            b'\'' => quoted_text.extend_from_slice(b"'\\''"),
+            _ => quoted_text.push(*byte),
+        }
+    }
+    quoted_text.push(b'\'');
+    quoted_text
+}
+
+// Quotes a name for the shell if needed, or always with "always_quote". Like coreutils, names
+// whose only special characters are spaces and single quotes are put in double quotes, which
+// reads better than '\'' (e.g. "it's").
+fn shell_quote(name: &[u8], always_quote: bool) -> Vec<u8> {
+    if always_quote == false && needs_shell_quoting(name) == false {
+        return name.to_vec();
+    }
+
+    let is_double_quotable = name.contains(&b'\'')
+        && get_valid_characters(name).all(|character| {
+            character == '\''
+                || character == ' '
+                || (!SHELL_SPECIAL_CHARACTERS.contains(character) && !character.is_control())
+        });
+    if is_double_quotable == true {
+        return [b"\"", name, b"\""].concat();
+    }
+    single_quote(name)
+}
+
+// Quotes a name for the shell, writing its nonprintable characters with the $'...' syntax of
+// bash, e.g. 'a'$'\n''b'. The quotes are opened and closed at the same places as coreutils, so
+// that e.g. a name starting with a nonprintable character begins with ''.
+fn shell_escape(units: &Vec<NameUnit>, always_quote: bool) -> Vec<u8> {
+    if units
+        .iter()
+        .all(|unit| get_printable_character(unit).is_some())
+    {
+        let name: String = units.iter().filter_map(get_printable_character).collect();
+        return shell_quote(name.as_bytes(), always_quote);
+    }
+
+    let mut quoted_name = vec![b'\''];
+    let mut is_escaping = false;
+
+    for unit in units {
+        match get_printable_character(unit) {
+            Some('\'') => {
+                quoted_name.extend_from_slice(b"'\\''");
+                is_escaping = false;
+            }
+            Some(character) => {
+                if is_escaping == true {
+                    quoted_name.extend_from_slice(b"''");
+                    is_escaping = false;
+                }
+                quoted_name.extend_from_slice(character.encode_utf8(&mut [0; 4]).as_bytes());
+            }
+            None => {
+                if is_escaping == false {
+                    quoted_name.extend_from_slice(b"'$'");
+                    is_escaping = true;
+                }
+                quoted_name.extend_from_slice(escape_unit(unit).as_bytes());
+            }
+        }
+    }
+    quoted_name.push(b'\'');
+    quoted_name
+}
+
+// Writes a name with C escape sequences, backslashes being doubled and the given characters
+// escaped with a backslash (the double quote for the c style, the space for the escape style).
+fn c_escape(units: &[NameUnit], escaped_characters: &str) -> String {
+    units
+        .iter()
+        .map(|unit| match get_printable_character(unit) {
+            Some('\\') => String::from("\\\\"),
+            Some(character) if escaped_characters.contains(character) => format!("\\{}", character),
+            Some(character) => character.to_string(),
+            None => escape_unit(unit),
+        })
+        .collect()
+}
+
+// Returns the name as printed in listings, according to the --quoting-style parameter (and -N,
+// -Q, -b and -q). The width of the columns is computed on this form. It is made of bytes, as the
+// styles that don't escape anything print names that aren't valid UTF-8 as they are.
+pub fn quote_name(name: &OsStr, parameters: &Parameters) -> Vec<u8> {
+    let units = get_name_units(name);
+
+    // For the shell styles, the quotes depend on the name itself, and the nonprintable characters
+    // are only replaced by "?" afterwards, like coreutils does.
+    match parameters.quoting_style {
+        QuotingStyle::Literal => get_literal_name(&units, parameters),
+        QuotingStyle::Shell => get_literal_name(
+            &get_name_units(OsStr::from_bytes(&shell_quote(name.as_bytes(), false))),
+            parameters,
+        ),
+        QuotingStyle::ShellAlways => get_literal_name(
+            &get_name_units(OsStr::from_bytes(&shell_quote(name.as_bytes(), true))),
+            parameters,
+        ),
+        QuotingStyle::ShellEscape => shell_escape(&units, false),
+        QuotingStyle::ShellEscapeAlways => shell_escape(&units, true),
+        QuotingStyle::C => format!("\"{}\"", c_escape(&units, "\"")).into_bytes(),
+        QuotingStyle::Escape => c_escape(&units, " ").into_bytes(),
+        QuotingStyle::Locale => format!("\u{2018}{}\u{2019}", c_escape(&units, "")).into_bytes(),
+    }
+}
+
+// Returns the name as written in error messages, e.g. "cannot access 'caf'$'\351'". Like
+// coreutils, it is always quoted with the shell-escape style, so that it names the file without
+// ambiguity whatever the characters it contains.
+pub fn quote_name_in_message(name: &OsStr) -> String {
+    String::from_utf8_lossy(&shell_escape(&get_name_units(name), true)).into_owned()
+}
+
+// Returns the name as written at the beginning of an error message, before a colon (e.g.
+// "dir: not listing already-listed directory"). It is only quoted when needed, like in the
+// shell-escape style, a colon in the name requiring quotes too.
+pub fn quote_name_before_colon(name: &OsStr) -> String {
+    let always_quote = name.as_bytes().contains(&b':');
+    String::from_utf8_lossy(&shell_escape(&get_name_units(name), always_quote)).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Quotes a name with the given style, the expected outputs being those of coreutils.
+    fn quote(name: &[u8], quoting_style: QuotingStyle, hide_control_characters: bool) -> Vec<u8> {
+        let mut parameters = Parameters::new();
+        parameters.quoting_style = quoting_style;
+        parameters.hide_control_characters = hide_control_characters;
+        quote_name(OsStr::from_bytes(name), &parameters)
+    }
+
+    #[test]
+    fn literal_style_prints_names_as_they_are() {
+        for name in [&b"plain"[..], b"a b", b"a\nb", b"caf\xe9", b"\x01start"] {
+            assert_eq!(quote(name, QuotingStyle::Literal, false), name);
+        }
+        assert_eq!(quote(b"a\nb", QuotingStyle::Literal, true), b"a?b");
+        assert_eq!(quote(b"caf\xe9", QuotingStyle::Literal, true), b"caf?");
+        assert_eq!(quote(b"tab\t", QuotingStyle::Literal, true), b"tab?");
+    }
+
+    #[test]
+    fn shell_style_quotes_only_when_needed() {
+        let expected: [(&[u8], &[u8]); 14] = [
+            (b"plain", b"plain"),
+            (b"a b", b"'a b'"),
+            (b"it's", b"\"it's\""),
+            (b"a\"b", b"'a\"b'"),
+            (b"a\nb", b"'a\nb'"),
+            (b"tab\t", b"'tab\t'"),
+            (b"caf\xe9", b"caf\xe9"),
+            (b"~home", b"'~home'"),
+            (b"#x", b"'#x'"),
+            (b"{", b"'{'"),
+            (b"back\\slash", b"'back\\slash'"),
+            (b"it's \"q\"", b"'it'\\''s \"q\"'"),
+            (b"$x", b"'$x'"),
+            (b"\x01start", b"\x01start"),
+        ];
+        for (name, quoted_name) in expected {
+            assert_eq!(quote(name, QuotingStyle::Shell, false), quoted_name);
+        }
+    }
+
+    #[test]
+    fn shell_style_decides_the_quotes_before_hiding_control_characters() {
+        assert_eq!(quote(b"\x01start", QuotingStyle::Shell, true), b"?start");
+        assert_eq!(quote(b"a\nb", QuotingStyle::Shell, true), b"'a?b'");
+        assert_eq!(quote(b"?x", QuotingStyle::Shell, true), b"'?x'");
+    }
+
+    #[test]
+    fn shell_always_style_quotes_every_name() {
+        let expected: [(&[u8], &[u8]); 5] = [
+            (b"plain", b"'plain'"),
+            (b"it's", b"\"it's\""),
+            (b"caf\xe9", b"'caf\xe9'"),
+            (b"\x01start", b"'\x01start'"),
+            (b"", b"''"),
+        ];
+        for (name, quoted_name) in expected {
+            assert_eq!(quote(name, QuotingStyle::ShellAlways, false), quoted_name);
+        }
+        assert_eq!(quote(b"a\x07b", QuotingStyle::ShellAlways, true), b"'a?b'");
+    }
+
+    #[test]
+    fn shell_escape_style_writes_nonprintable_characters_in_dollar_quotes() {
+        let expected: [(&[u8], &[u8]); 9] = [
+            (b"plain", b"plain"),
+            (b"a b", b"'a b'"),
+            (b"it's", b"\"it's\""),
+            (b"a\nb", b"'a'$'\\n''b'"),
+            (b"tab\t", b"'tab'$'\\t'"),
+            (b"caf\xe9", b"'caf'$'\\351'"),
+            (b"\x01start", b"''$'\\001''start'"),
+            (b"a'b\nc", b"'a'\\''b'$'\\n''c'"),
+            (b"\n'", b"''$'\\n'\\'''"),
+        ];
+        for (name, quoted_name) in expected {
+            assert_eq!(quote(name, QuotingStyle::ShellEscape, false), quoted_name);
+            // Nothing is left to hide once the characters are escaped.
+            assert_eq!(quote(name, QuotingStyle::ShellEscape, true), quoted_name);
+        }
+    }
+
+    #[test]
+    fn shell_escape_always_style_quotes_every_name() {
+        assert_eq!(
+            quote(b"plain", QuotingStyle::ShellEscapeAlways, false),
+            b"'plain'"
+        );
+        assert_eq!(
+            quote(b"it's", QuotingStyle::ShellEscapeAlways, false),
+            b"\"it's\""
+        );
+        assert_eq!(
+            quote(b"a\nb", QuotingStyle::ShellEscapeAlways, false),
+            b"'a'$'\\n''b'"
+        );
+    }
+
+    #[test]
+    fn c_style_escapes_like_a_c_string() {
+        let expected: [(&[u8], &[u8]); 7] = [
+            (b"plain", b"\"plain\""),
+            (b"a\"b", b"\"a\\\"b\""),
+            (b"a\nb", b"\"a\\nb\""),
+            (b"caf\xe9", b"\"caf\\351\""),
+            (b"back\\slash", b"\"back\\\\slash\""),
+            (b"it's \"q\"", b"\"it's \\\"q\\\"\""),
+            (b"\x01start", b"\"\\001start\""),
+        ];
+        for (name, quoted_name) in expected {
+            assert_eq!(quote(name, QuotingStyle::C, false), quoted_name);
+        }
+    }
+
+    #[test]
+    fn escape_style_escapes_spaces_without_quoting() {
+        let expected: [(&[u8], &[u8]); 7] = [
+            (b"plain", b"plain"),
+            (b"a b", b"a\\ b"),
+            (b"a\"b", b"a\"b"),
+            (b"tab\t", b"tab\\t"),
+            (b"caf\xe9", b"caf\\351"),
+            (b"back\\slash", b"back\\\\slash"),
+            (b"\x01start", b"\\001start"),
+        ];
+        for (name, quoted_name) in expected {
+            assert_eq!(quote(name, QuotingStyle::Escape, false), quoted_name);
+        }
+    }
+
+    #[test]
+    fn locale_style_uses_typographic_quotes() {
+        let expected: [(&[u8], &str); 5] = [
+            (b"plain", "\u{2018}plain\u{2019}"),
+            (b"a\nb", "\u{2018}a\\nb\u{2019}"),
+            (b"caf\xe9", "\u{2018}caf\\351\u{2019}"),
+            (b"back\\slash", "\u{2018}back\\\\slash\u{2019}"),
+            (b"it's \"q\"", "\u{2018}it's \"q\"\u{2019}"),
+        ];
+        for (name, quoted_name) in expected {
+            assert_eq!(
+                quote(name, QuotingStyle::Locale, false),
+                quoted_name.as_bytes()
+            );
+        }
+    }
+
+    #[test]
+    fn names_in_messages_are_quoted_like_coreutils() {
+        let name = OsStr::from_bytes(b"caf\xe9");
+        assert_eq!(quote_name_in_message(name), "'caf'$'\\351'");
+        assert_eq!(quote_name_in_message(OsStr::new("plain")), "'plain'");
+        assert_eq!(quote_name_in_message(OsStr::new("it's")), "\"it's\"");
+        assert_eq!(quote_name_before_colon(OsStr::new("plain")), "plain");
+        assert_eq!(quote_name_before_colon(OsStr::new("a:b")), "'a:b'");
+        assert_eq!(quote_name_before_colon(name), "'caf'$'\\351'");
+    }
+
+    #[test]
+    fn c_escape_uses_octal_for_characters_without_a_sequence() {
+        let units = get_name_units(OsStr::from_bytes("\x1b[0m\u{7f}é".as_bytes()));
+        assert_eq!(c_escape(&units, ""), "\\033[0m\\177é");
+    }
+}
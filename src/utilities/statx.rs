@@ -0,0 +1,119 @@
+#![allow(clippy::bool_comparison)]
+
+use std::fs::Metadata;
+use std::path::Path;
+use std::time::SystemTime;
+
+// The metadata std::fs::Metadata does not give access to. On Linux, it comes from the statx()
+// system call (see "man 2 statx"); elsewhere, or on kernels older than 4.11, only the birth time is
+// filled, when the platform provides it. The libc crate only declares statx() for glibc, so other
+// C libraries, like musl, use the fallback as well.
+#[derive(Debug, Clone, Default)]
+pub struct ExtendedMetadata {
+    pub birth_time: Option<SystemTime>,
+    // The STATX_ATTR_* flags set on the file, and the ones the file system supports at all.
+    pub attributes: u64,
+    pub attributes_mask: u64,
+    // Not shown by any column yet.
+    #[allow(dead_code)]
+    pub mount_id: Option<u64>,
+}
+
+// Fills ExtendedMetadata from the standard metadata, which is all there is without statx().
+fn get_fallback_metadata(metadata: Metadata) -> ExtendedMetadata {
+    ExtendedMetadata {
+        birth_time: metadata.created().ok(),
+        ..Default::default()
+    }
+}
+
+// Returns the extended metadata of the given file, describing the symbolic link itself unless
+// "follow_symbolic_links" is true. Returns None if the file can't be accessed.
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+pub fn get_extended_metadata(path: &Path, follow_symbolic_links: bool) -> Option<ExtendedMetadata> {
+    use std::ffi::CString;
+    use std::mem::MaybeUninit;
+    use std::os::unix::ffi::OsStrExt;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    // Set once statx() turned out not to exist, so that it isn't tried again for every file.
+    static STATX_UNSUPPORTED: AtomicBool = AtomicBool::new(false);
+
+    let fallback = || {
+        let metadata = if follow_symbolic_links == true {
+            path.metadata()
+        } else {
+            path.symlink_metadata()
+        };
+        metadata.ok().map(get_fallback_metadata)
+    };
+
+    if STATX_UNSUPPORTED.load(Ordering::Relaxed) == true {
+        return fallback();
+    }
+    let path_name = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let flags = if follow_symbolic_links == true {
+        libc::AT_STATX_SYNC_AS_STAT
+    } else {
+        libc::AT_STATX_SYNC_AS_STAT | libc::AT_SYMLINK_NOFOLLOW
+    };
+    let mut buffer = MaybeUninit::<libc::statx>::zeroed();
+
+    // SAFETY: the path is NUL-terminated and the buffer is large enough for a statx structure.
+    let result = unsafe {
+        libc::statx(
+            libc::AT_FDCWD,
+            path_name.as_ptr(),
+            flags,
+            libc::STATX_BASIC_STATS | libc::STATX_BTIME | libc::STATX_MNT_ID,
+            buffer.as_mut_ptr(),
+        )
+    };
+    if result != 0 {
+        let error = std::io::Error::last_os_error();
+        return match error.raw_os_error() {
+            Some(libc::ENOSYS) | Some(libc::EPERM) => {
+                // Old kernels don't have statx(), and some seccomp filters forbid it.
+                STATX_UNSUPPORTED.store(true, Ordering::Relaxed);
+                fallback()
+            }
+            _ => None,
+        };
+    }
+
+    // SAFETY: statx() succeeded, so it filled the buffer.
+    let statx = unsafe { buffer.assume_init() };
+    let birth_time = if statx.stx_mask & libc::STATX_BTIME != 0 {
+        let since_epoch = Duration::new(
+            statx.stx_btime.tv_sec.unsigned_abs(),
+            statx.stx_btime.tv_nsec,
+        );
+        if statx.stx_btime.tv_sec >= 0 {
+            UNIX_EPOCH.checked_add(since_epoch)
+        } else {
+            UNIX_EPOCH.checked_sub(since_epoch)
+        }
+    } else {
+        None
+    };
+
+    Some(ExtendedMetadata {
+        birth_time,
+        attributes: statx.stx_attributes,
+        attributes_mask: statx.stx_attributes_mask,
+        mount_id: Some(statx.stx_mnt_id).filter(|_| statx.stx_mask & libc::STATX_MNT_ID != 0),
+    })
+}
+
+// Returns the extended metadata of the given file, describing the symbolic link itself unless
+// "follow_symbolic_links" is true. Returns None if the file can't be accessed.
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+pub fn get_extended_metadata(path: &Path, follow_symbolic_links: bool) -> Option<ExtendedMetadata> {
+    let metadata = if follow_symbolic_links == true {
+        path.metadata()
+    } else {
+        path.symlink_metadata()
+    };
+    metadata.ok().map(get_fallback_metadata)
+}
@@ -1,4 +1,11 @@
-use std::fs::{Metadata, Permissions};
+#![allow(clippy::bool_comparison)]
+
+use crate::utilities::colors::*;
+use crate::utilities::quoting::*;
+use std::ffi::OsString;
+use std::fs::{FileType, Metadata, Permissions};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
 use users::{get_group_by_gid, get_user_by_uid};
 
 use std::time::SystemTime;
@@ -19,46 +26,108 @@ fn number_of_blocks(metadata: &Metadata) -> u64 {
 #[cfg(target_os = "linux")]
 use std::os::linux::fs::MetadataExt;
 #[cfg(target_os = "linux")]
-fn number_of_links(metadata: &Metadata) {
+use std::os::unix::fs::MetadataExt as _;
+#[cfg(target_os = "linux")]
+fn number_of_links(metadata: &Metadata) -> u64 {
     (metadata as &dyn MetadataExt).st_nlink()
 }
 #[cfg(target_os = "linux")]
-fn number_of_blocks(metadata: &Metadata) {
+fn number_of_blocks(metadata: &Metadata) -> u64 {
     (metadata as &dyn MetadataExt).st_blocks()
 }
 
-fn get_username(id: u32) -> String {
-    get_user_by_uid(id)
-        .unwrap()
-        .name()
-        .to_string_lossy()
-        .into_owned()
+// Owner and group names are only resolved when printed, as the lookup is costly and only the long
+// format needs them.
+// Ids without a name (e.g. files extracted from an archive made elsewhere) are shown as numbers.
+pub fn get_username(id: u32) -> String {
+    get_user_by_uid(id).map_or(id.to_string(), |user| {
+        user.name().to_string_lossy().into_owned()
+    })
+}
+
+pub fn get_group(id: u32) -> String {
+    get_group_by_gid(id).map_or(id.to_string(), |group| {
+        group.name().to_string_lossy().into_owned()
+    })
 }
 
-fn get_group(id: u32) -> String {
-    get_group_by_gid(id)
-        .unwrap()
-        .name()
-        .to_string_lossy()
-        .into_owned()
+// Which suffix, if any, is appended to file names (see the -F, -p and --file-type parameters).
+#[derive(PartialEq)]
+pub enum IndicatorStyle {
+    None,
+    Slash,
+    FileType,
+    Classify,
+}
+
+// Which symbolic links are followed, see the -L and -H parameters.
+#[derive(PartialEq)]
+pub enum DereferenceMode {
+    Never,
+    CommandLineSymlinkToDir,
+    CommandLine,
+    Always,
+}
+
+// How file names are printed, see the --quoting-style parameter.
+#[derive(PartialEq)]
+pub enum QuotingStyle {
+    Literal,
+    Shell,
+    ShellAlways,
+    ShellEscape,
+    ShellEscapeAlways,
+    C,
+    Escape,
+    Locale,
 }
 
 pub struct Parameters {
     pub include_dot_files: bool,
+    pub include_almost_all_dot_files: bool,
+    pub ignore_patterns: Vec<OsString>,
     pub long_format: bool,
     pub reverse_order: bool,
     pub recursive_listing: bool,
+    pub tree: bool,
     pub last_modified_order: bool,
+    pub birth_time: bool,
+    pub git_status: bool,
+    pub attributes: bool,
+    pub security_context: bool,
+    pub capabilities: bool,
+    pub indicator_style: IndicatorStyle,
+    pub quoting_style: QuotingStyle,
+    pub hide_control_characters: bool,
+    pub colors: Option<ColorDatabase>,
+    pub dereference: DereferenceMode,
+    pub max_depth: Option<usize>,
+    pub threads: usize,
 }
 
 impl Parameters {
     pub fn new() -> Parameters {
         Parameters {
             include_dot_files: false,
+            include_almost_all_dot_files: false,
+            ignore_patterns: Vec::new(),
             long_format: false,
             reverse_order: false,
             recursive_listing: false,
+            tree: false,
             last_modified_order: false,
+            birth_time: false,
+            git_status: false,
+            attributes: false,
+            security_context: false,
+            capabilities: false,
+            indicator_style: IndicatorStyle::None,
+            quoting_style: get_default_quoting_style(),
+            hide_control_characters: hides_control_characters_by_default(),
+            colors: get_color_database(&ColorMode::Auto),
+            dereference: DereferenceMode::CommandLineSymlinkToDir,
+            max_depth: None,
+            threads: 1,
         }
     }
 }
@@ -67,31 +136,120 @@ impl Parameters {
 pub struct File {
     pub is_dir: bool,
     pub is_symbolic_link: bool,
+    pub is_broken_symbolic_link: bool,
+    pub is_fifo: bool,
+    pub is_socket: bool,
     pub file_mode: Permissions,
     pub number_of_links: u64,
-    pub owner_name: String,
-    pub group_name: String,
+    pub owner_id: u32,
+    pub group_id: u32,
     pub number_of_bytes: u64,
-    pub last_modified: SystemTime,
-    pub path_name: String,
-    pub extended_attributes: bool,
+    // None if the file system doesn't record it.
+    pub last_modified: Option<SystemTime>,
+    // The name as found on disk, which may not be valid UTF-8, see quote_name().
+    pub path_name: OsString,
+    pub full_path: PathBuf,
     pub blocks: u64,
+    // Only read with the -Z parameter, see fill_security_contexts().
+    pub security_context: Option<String>,
+    // False for the placeholders of files that couldn't be stat'ed, see File::placeholder().
+    pub is_accessible: bool,
 }
 
 impl File {
-    pub fn new(path: String, metadata: Metadata, attributes: bool) -> File {
+    // The metadata is expected to come from symlink_metadata(), so that symbolic links are
+    // described themselves rather than through their target. If "dereference" is true, the
+    // target's metadata is used instead, unless the link is broken.
+    pub fn new(
+        path: OsString,
+        full_path: &Path,
+        mut metadata: Metadata,
+        dereference: bool,
+    ) -> File {
+        if dereference == true && metadata.is_symlink() {
+            if let Ok(target_metadata) = full_path.metadata() {
+                metadata = target_metadata;
+            }
+        }
+
         File {
             is_dir: metadata.is_dir(),
             is_symbolic_link: metadata.is_symlink(),
+            is_broken_symbolic_link: metadata.is_symlink() && full_path.metadata().is_err(),
+            is_fifo: metadata.file_type().is_fifo(),
+            is_socket: metadata.file_type().is_socket(),
             file_mode: metadata.permissions(),
             number_of_links: number_of_links(&metadata),
-            owner_name: get_username(metadata.uid()),
-            group_name: get_group(metadata.gid()),
+            owner_id: metadata.uid(),
+            group_id: metadata.gid(),
             number_of_bytes: metadata.len(),
-            last_modified: metadata.modified().unwrap(),
+            last_modified: metadata.modified().ok(),
             path_name: path,
-            extended_attributes: attributes,
+            full_path: full_path.to_path_buf(),
             blocks: number_of_blocks(&metadata),
+            security_context: None,
+            is_accessible: true,
         }
     }
+
+    // Builds a File from its type alone, as given by the directory entry (d_type), without any
+    // stat. Only usable when the output format needs nothing else, see needs_metadata(): the
+    // mode only holds the type bits, and the other fields are left empty.
+    pub fn from_file_type(path: OsString, full_path: &Path, file_type: FileType) -> File {
+        File::without_metadata(path, full_path, Some(file_type), true)
+    }
+
+    // Builds the File of an entry that couldn't be stat'ed (e.g. deleted while being listed), so
+    // that it is still listed, with "?" in place of its metadata. Its type is known if the
+    // directory entry gave it.
+    pub fn placeholder(path: OsString, full_path: &Path, file_type: Option<FileType>) -> File {
+        File::without_metadata(path, full_path, file_type, false)
+    }
+
+    fn without_metadata(
+        path: OsString,
+        full_path: &Path,
+        file_type: Option<FileType>,
+        is_accessible: bool,
+    ) -> File {
+        File {
+            is_dir: file_type.is_some_and(|file_type| file_type.is_dir()),
+            is_symbolic_link: file_type.is_some_and(|file_type| file_type.is_symlink()),
+            is_broken_symbolic_link: false,
+            is_fifo: file_type.is_some_and(|file_type| file_type.is_fifo()),
+            is_socket: file_type.is_some_and(|file_type| file_type.is_socket()),
+            file_mode: Permissions::from_mode(
+                file_type.map_or(0, |file_type| file_type_mode(&file_type)),
+            ),
+            number_of_links: 0,
+            owner_id: 0,
+            group_id: 0,
+            number_of_bytes: 0,
+            last_modified: None,
+            path_name: path,
+            full_path: full_path.to_path_buf(),
+            blocks: 0,
+            security_context: None,
+            is_accessible,
+        }
+    }
+}
+
+// Returns the type bits of a mode (S_IFDIR, S_IFLNK...) matching the given file type.
+fn file_type_mode(file_type: &FileType) -> u32 {
+    if file_type.is_dir() {
+        0o040000
+    } else if file_type.is_symlink() {
+        0o120000
+    } else if file_type.is_fifo() {
+        0o010000
+    } else if file_type.is_socket() {
+        0o140000
+    } else if file_type.is_block_device() {
+        0o060000
+    } else if file_type.is_char_device() {
+        0o020000
+    } else {
+        0o100000
+    }
 }
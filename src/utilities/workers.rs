@@ -0,0 +1,117 @@
+#![allow(clippy::bool_comparison)]
+
+use crate::*;
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::channel;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+// A task run by any of the threads, which gives it the command's parameters.
+pub type Task<T> = Box<dyn FnOnce(&Parameters) -> T + Send>;
+
+type Job = Task<()>;
+
+// The jobs waiting for a thread, and whether the workers have to stop once there are none left.
+struct Queue {
+    jobs: VecDeque<Job>,
+    is_closed: bool,
+}
+
+// A single queue is shared by the whole command, so that no more threads than the --threads
+// parameter asks for ever run, however many directories are read at once.
+static QUEUE: Mutex<Queue> = Mutex::new(Queue {
+    jobs: VecDeque::new(),
+    is_closed: false,
+});
+static JOB_ADDED: Condvar = Condvar::new();
+
+// Runs the command with the threads of the --threads parameter: the current one, and as many
+// workers as needed to make up the rest. The workers stop once the command is done.
+pub fn run_with_workers(parameters: &Parameters, command: impl FnOnce()) {
+    thread::scope(|scope| {
+        for _ in 1..parameters.threads {
+            scope.spawn(|| {
+                while let Some(job) = wait_for_job() {
+                    job(parameters);
+                }
+            });
+        }
+
+        // The workers also have to stop if the command panics, or the scope would never end.
+        let result = panic::catch_unwind(AssertUnwindSafe(command));
+        QUEUE.lock().unwrap().is_closed = true;
+        JOB_ADDED.notify_all();
+        if let Err(panic) = result {
+            panic::resume_unwind(panic);
+        }
+    });
+}
+
+// Waits until there is a job to run, returning None once the command is done.
+fn wait_for_job() -> Option<Job> {
+    let mut queue = QUEUE.lock().unwrap();
+
+    loop {
+        if let Some(job) = queue.jobs.pop_front() {
+            return Some(job);
+        }
+        if queue.is_closed == true {
+            return None;
+        }
+        queue = JOB_ADDED.wait(queue).unwrap();
+    }
+}
+
+// Runs the tasks on the worker threads, returning their results in order. While waiting for them,
+// the current thread runs the queued jobs itself, so that the tasks started by a worker (e.g. the
+// chunks of a large directory) never wait for a free thread. The errors reported by each task are
+// reported in order as well, once all of them are done.
+pub fn run_in_parallel<T: Send + 'static>(tasks: Vec<Task<T>>, parameters: &Parameters) -> Vec<T> {
+    let number_of_tasks = tasks.len();
+    let (sender, receiver) = channel();
+    {
+        let mut queue = QUEUE.lock().unwrap();
+        for (index, task) in tasks.into_iter().enumerate() {
+            let sender = sender.clone();
+            queue
+                .jobs
+                .push_back(Box::new(move |parameters: &Parameters| {
+                    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                        collect_errors(|| task(parameters))
+                    }));
+                    // The receiver is only gone if another task panicked.
+                    let _ = sender.send((index, result));
+                }));
+        }
+    }
+    JOB_ADDED.notify_all();
+
+    let mut results: Vec<Option<(T, Vec<ReportedError>)>> =
+        (0..number_of_tasks).map(|_| None).collect();
+    for _ in 0..number_of_tasks {
+        let (index, result) = loop {
+            if let Ok(message) = receiver.try_recv() {
+                break message;
+            }
+            let job = QUEUE.lock().unwrap().jobs.pop_front();
+            match job {
+                Some(job) => job(parameters),
+                None => break receiver.recv().unwrap(),
+            }
+        };
+        match result {
+            Ok(result) => results[index] = Some(result),
+            Err(panic) => panic::resume_unwind(panic),
+        }
+    }
+
+    results
+        .into_iter()
+        .flatten()
+        .map(|(result, errors)| {
+            report_errors(errors);
+            result
+        })
+        .collect()
+}